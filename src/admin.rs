@@ -0,0 +1,203 @@
+// admin.rs
+// `h3x admin ...`: edit the persistent client registry in place. A running
+// server picks the changes up on its next reload (file change or SIGHUP).
+// `dlq` commands open the event queue directly, so the server must be stopped
+// (sled allows one process per database).
+//
+// h3x admin list
+// h3x admin add <client_id> [--token <token>] [--cert-subject <name>]
+// h3x admin remove <client_id>
+// h3x admin grant <client_id> <namespace> [read|write|rw]
+// h3x admin revoke <client_id> <namespace> [read|write|rw]
+// h3x admin rotate <client_id> [--token <token>]
+// h3x admin dlq list <namespace> [--group <group>]
+// h3x admin dlq replay <namespace> [event_id] [--group <group>]
+// h3x admin dlq purge <namespace> [event_id] [--group <group>]
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::server::params::ServerParams;
+use crate::state::queue::{valid_group, EventQueue, DEFAULT_GROUP};
+use crate::state::registry::{check_namespace, generate_token, registry_key, Access, ClientMetadata, RegistryStore};
+
+pub const USAGE: &str = "\
+Usage: h3x admin <command>
+  list
+  add <client_id> [--token <token>] [--cert-subject <name>]
+  remove <client_id>
+  grant <client_id> <namespace> [read|write|rw]
+  revoke <client_id> <namespace> [read|write|rw]
+  rotate <client_id> [--token <token>]
+  dlq list <namespace> [--group <group>]
+  dlq replay <namespace> [event_id] [--group <group>]   (all when no id; server must be stopped)
+  dlq purge <namespace> [event_id] [--group <group>]    (all when no id; server must be stopped)
+  dlq commands act on the \"default\" consumer group unless --group is given";
+
+/// Run one admin command against the server's stores. `args` excludes the leading `admin`.
+pub fn run(params: &ServerParams, args: &[String]) -> Result<()> {
+    let (command, rest) = args.split_first().ok_or_else(|| anyhow!("Missing admin command"))?;
+    if command == "dlq" {
+        return run_dlq(params, rest);
+    }
+
+    let store = RegistryStore::new(&params.registry_path);
+    let mut clients = store.load()?;
+
+    match command.as_str() {
+        "list" => {
+            if clients.is_empty() {
+                println!("(no clients registered in {})", store.path().display());
+            }
+            let mut entries: Vec<_> = clients.values().collect();
+            entries.sort_by(|a, b| a.client_id.cmp(&b.client_id));
+            for meta in entries {
+                println!(
+                    "{}  read={:?}  write={:?}{}",
+                    meta.client_id,
+                    meta.read_namespaces,
+                    meta.write_namespaces,
+                    meta.cert_subject
+                        .as_deref()
+                        .map(|s| format!("  cert_subject={s}"))
+                        .unwrap_or_default(),
+                );
+            }
+            return Ok(());
+        }
+        "add" => {
+            let ([id], opts) = positional::<1>(rest)?;
+            let key = registry_key(id);
+            if clients.contains_key(&key) {
+                bail!("Client {id} already exists");
+            }
+
+            let (token, generated) = token_arg(opts)?;
+            let mut meta = ClientMetadata::new(id.to_string(), &token);
+            meta.cert_subject = option(opts, "--cert-subject")?.map(str::to_string);
+            clients.insert(key, meta);
+            println!("➕ Added client {id}");
+            if generated {
+                println!("🔑 Token (shown once): {token}");
+            }
+        }
+        "remove" => {
+            let ([id], _) = positional::<1>(rest)?;
+            if clients.remove(&registry_key(id)).is_none() {
+                bail!("No such client: {id}");
+            }
+            println!("➖ Removed client {id}");
+        }
+        "grant" | "revoke" => {
+            let ([id, ns], opts) = positional::<2>(rest)?;
+            let access = match opts.first().map(String::as_str) {
+                None | Some("rw") => vec![Access::Read, Access::Write],
+                Some("read") => vec![Access::Read],
+                Some("write") => vec![Access::Write],
+                Some(other) => bail!("Unknown access {other:?}, expected read, write or rw"),
+            };
+            let meta = clients
+                .get_mut(&registry_key(id))
+                .ok_or_else(|| anyhow!("No such client: {id}"))?;
+
+            for a in &access {
+                if command == "grant" {
+                    meta.grant(ns.as_str(), *a)?;
+                } else {
+                    meta.revoke(ns, *a);
+                }
+            }
+            println!("🔧 {command} {access:?} on {ns} for {id}");
+        }
+        "rotate" => {
+            let ([id], opts) = positional::<1>(rest)?;
+            let meta = clients
+                .get_mut(&registry_key(id))
+                .ok_or_else(|| anyhow!("No such client: {id}"))?;
+
+            let (token, generated) = token_arg(opts)?;
+            meta.set_token(&token);
+            println!("🔄 Rotated token for {id}");
+            if generated {
+                println!("🔑 Token (shown once): {token}");
+            }
+        }
+        other => bail!("Unknown admin command: {other}"),
+    }
+
+    store.save(&clients)
+}
+
+/// `h3x admin dlq ...`: inspect, replay or purge a consumer group's dead-lettered events.
+fn run_dlq(params: &ServerParams, args: &[String]) -> Result<()> {
+    let ([command, ns], rest) = positional::<2>(args)?;
+    let event_id = rest.first().map(String::as_str).filter(|a| !a.starts_with("--"));
+    let group = option(rest, "--group")?.unwrap_or(DEFAULT_GROUP);
+    check_namespace(ns)?;
+    if !valid_group(group) {
+        bail!("Invalid group {group:?}: 1–64 ASCII letters, digits, '-' or '_'");
+    }
+    // Replayed events must get the namespace's TTL and retention, as on the server.
+    let queue = EventQueue::new(params.queue_path())
+        .map_err(|e| anyhow!("Failed to open queue {} (is the server running?): {e}", params.queue_path().display()))?
+        .with_visibility_timeout(params.visibility_timeout)
+        .with_policies(params.policies.clone());
+
+    match command.as_str() {
+        "list" => {
+            let letters = queue.dead_letters(ns, group)?;
+            if letters.is_empty() {
+                println!("(no dead-lettered events in {ns} for group {group})");
+            }
+            for letter in letters {
+                let Some(ev) = &letter.event else { continue };
+                println!(
+                    "{}  type={}  attempts={}  dead_at_ms={}  message={:?}",
+                    ev.id, ev.r#type, letter.attempts, letter.dead_at_ms, ev.message,
+                );
+                for reason in &letter.reasons {
+                    println!("    - {reason}");
+                }
+            }
+        }
+        "replay" => {
+            let n = queue.replay_dead_letters(ns, group, event_id)?;
+            println!("♻️ Replayed {n} dead-lettered event(s) into {ns} for group {group}");
+        }
+        "purge" => {
+            let n = queue.purge_dead_letters(ns, group, event_id)?;
+            println!("🗑️ Purged {n} dead-lettered event(s) from {ns} for group {group}");
+        }
+        other => bail!("Unknown dlq command: {other}"),
+    }
+
+    queue.db.flush()?;
+    Ok(())
+}
+
+/// Split off `N` required positional arguments.
+fn positional<const N: usize>(args: &[String]) -> Result<(&[String; N], &[String])> {
+    if args.len() < N {
+        bail!("Expected {N} argument(s)");
+    }
+    let (head, rest) = args.split_at(N);
+    Ok((head.try_into().expect("length checked"), rest))
+}
+
+/// `--token` if given, else a freshly generated one (flagged so it can be shown).
+fn token_arg(args: &[String]) -> Result<(String, bool)> {
+    Ok(match option(args, "--token")? {
+        Some(t) => (t.to_string(), false),
+        None => (generate_token(), true),
+    })
+}
+
+/// Value following `flag`, if present.
+fn option<'a>(args: &'a [String], flag: &str) -> Result<Option<&'a str>> {
+    match args.iter().position(|a| a == flag) {
+        Some(i) => args
+            .get(i + 1)
+            .map(|v| Some(v.as_str()))
+            .ok_or_else(|| anyhow!("{flag} needs a value")),
+        None => Ok(None),
+    }
+}
@@ -1,31 +1,35 @@
 use anyhow::Result;
 use chrono::Utc;
-use sled::Db;
 use std::collections::HashMap;
 use uuid::Uuid;
 
+use h3x::server::builder::ServerBuilder;
+use h3x::state::queue::EventQueue;
 use h3x::protocol::h3x::{
     frame, // the module that prost creates for the `oneof` payload
     Event,
     Frame,
     FrameType,
 };
-use prost::Message;
+use h3x::protocol::version::PROTO_VERSION;
 
-const PROTO_VERSION: u32 = 1;
 
 fn main() -> Result<()> {
-    let db: Db = sled::open("data/event_queue.db")?;
+    dotenv::dotenv().ok();
+    let params = ServerBuilder::from_env()
+        .and_then(|b| b.build())
+        .map_err(anyhow::Error::msg)?;
+    let queue = EventQueue::new(params.queue_path())?;
 
-    let key = inject_event(&db, "env_namespace")?;
-    println!("✅ Injected test event into sled under key: {key}");
+    let (id, seq) = inject_event(&queue, "env_namespace")?;
+    println!("✅ Injected test event {id} into sled at seq: {seq}");
 
     Ok(())
 }
 
-/// Create a sample Event, wrap it in a Frame, and persist to sled.
-/// Returns the sled key used: "{namespace}:{uuid}".
-fn inject_event(db: &Db, namespace: &str) -> Result<String> {
+/// Create a sample Event, wrap it in a Frame, and persist it through the queue.
+/// Returns the event id and the sequence number it was stored under.
+fn inject_event(queue: &EventQueue, namespace: &str) -> Result<(String, u64)> {
     let id = Uuid::new_v4().to_string();
 
     // Build the Event (prost-generated struct)
@@ -43,6 +47,10 @@ fn inject_event(db: &Db, namespace: &str) -> Result<String> {
             meta.insert("env".into(), "production".into());
             meta
         },
+        offset: 0, // assigned by the server on delivery
+        deliver_at: 0, // deliver right away
+        delay_ms: 0,
+        expires_at: 0, // namespace TTL, if any
     };
 
     // Wrap it in a Frame envelope (version + type + oneof payload)
@@ -53,15 +61,11 @@ fn inject_event(db: &Db, namespace: &str) -> Result<String> {
         payload: Some(frame::Payload::Event(event)),
     };
 
-    // Serialize with plain protobuf encoding (no extra length prefix).
-    // This is ideal if your sled values are single messages per key.
-    let bytes = frame.encode_to_vec();
+    // Same path the server uses for published events, so fetch/ack see it.
+    let seq = queue
+        .enqueue(&frame)?
+        .ok_or_else(|| anyhow::anyhow!("queue rejected non-Event frame"))?;
+    queue.db.flush()?;
 
-    // Key shape expected by your replay code
-    let key = format!("{namespace}:{id}");
-
-    db.insert(key.as_bytes(), bytes)?;
-    db.flush()?;
-
-    Ok(key)
+    Ok((id, seq))
 }
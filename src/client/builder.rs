@@ -1,9 +1,32 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::{env_parse, FileConfig};
+use crate::client::handler::EventHandler;
 use crate::client::params::ClientParams;
+use crate::client::publisher::PublisherConfig;
 
 pub struct ClientBuilder {
     client_id: String,
     namespaces: Vec<String>,
     token: Option<String>,
+    group: Option<String>,
+    publish: PublisherConfig,
+    handler: Option<Arc<dyn EventHandler>>,
+    processed_path: Option<PathBuf>,
+    remote_addr: Option<SocketAddr>,
+    server_name: Option<String>,
+    ca_path: Option<PathBuf>,
+    cert_path: Option<PathBuf>,
+    key_path: Option<PathBuf>,
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ClientBuilder {
@@ -12,9 +35,23 @@ impl ClientBuilder {
             client_id: "client_id:default".into(),
             namespaces: vec![],
             token: None,
+            group: None,
+            publish: PublisherConfig::default(),
+            handler: None,
+            processed_path: None,
+            remote_addr: None,
+            server_name: None,
+            ca_path: None,
+            cert_path: None,
+            key_path: None,
         }
     }
 
+    /// Defaults, then the `[client]` section of the `H3X_CONFIG` file, then `H3X_*` env vars.
+    pub fn from_env() -> Result<Self, String> {
+        Self::new().file(&FileConfig::from_env()?).env()
+    }
+
     pub fn client_id<T: Into<String>>(mut self, id: T) -> Self {
         self.client_id = id.into();
         self
@@ -35,12 +72,125 @@ impl ClientBuilder {
         self
     }
 
+    /// Consume as a member of `group`: each event goes to one member of the
+    /// group, and every group gets its own copy. Defaults to the server's default group.
+    pub fn group<T: Into<String>>(mut self, group: T) -> Self {
+        self.group = Some(group.into());
+        self
+    }
+
+    /// Flush published events once this many are buffered.
+    pub fn publish_batch_size(mut self, max: usize) -> Self {
+        self.publish.max_batch = max.max(1);
+        self
+    }
+
+    /// Flush a partial publish batch after this long.
+    pub fn publish_linger(mut self, linger: Duration) -> Self {
+        self.publish.linger = linger;
+        self
+    }
+
+    /// Fail a publish that is neither acked nor rejected within `timeout`. Defaults to 30s.
+    pub fn publish_ack_timeout(mut self, timeout: Duration) -> Self {
+        self.publish.ack_timeout = timeout;
+        self
+    }
+
+    /// Run `handler` on every received event; its result decides ack/nack/pending.
+    /// Defaults to logging and acking.
+    pub fn handler<H: EventHandler + 'static>(mut self, handler: H) -> Self {
+        self.handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Record handled events in a local sled store at `path` and ack
+    /// redeliveries of them without running the handler again.
+    pub fn exactly_once<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.processed_path = Some(path.into());
+        self
+    }
+
+    /// Server address to dial. Defaults to `127.0.0.1:5000`.
+    pub fn remote_addr(mut self, addr: SocketAddr) -> Self {
+        self.remote_addr = Some(addr);
+        self
+    }
+
+    /// TLS server name (SNI) to verify. Defaults to `localhost`.
+    pub fn server_name<T: Into<String>>(mut self, name: T) -> Self {
+        self.server_name = Some(name.into());
+        self
+    }
+
+    /// CA bundle (PEM, or a single DER certificate) used to verify the server.
+    /// Defaults to `cert.der`, the certificate the server writes in dev mode.
+    pub fn ca_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.ca_path = Some(path.into());
+        self
+    }
+
+    /// Client certificate chain and private key for servers that require mTLS.
+    pub fn client_cert<P: Into<PathBuf>, K: Into<PathBuf>>(mut self, cert_path: P, key_path: K) -> Self {
+        self.cert_path = Some(cert_path.into());
+        self.key_path = Some(key_path.into());
+        self
+    }
+
+    /// Apply the `[client]` section of a config file.
+    pub fn file(mut self, config: &FileConfig) -> Self {
+        let c = &config.client;
+        self.remote_addr = c.remote_addr.or(self.remote_addr);
+        self.server_name = c.server_name.clone().or(self.server_name);
+        self.ca_path = c.ca_path.clone().or(self.ca_path);
+        self.cert_path = c.cert_path.clone().or(self.cert_path);
+        self.key_path = c.key_path.clone().or(self.key_path);
+        self.group = c.group.clone().or(self.group);
+        self.processed_path = c.processed_path.clone().or(self.processed_path);
+        self
+    }
+
+    /// Apply `H3X_REMOTE_ADDR`, `H3X_SERVER_NAME`, `H3X_CA_PATH`,
+    /// `H3X_CLIENT_CERT_PATH`, `H3X_CLIENT_KEY_PATH`, `H3X_CLIENT_GROUP` and
+    /// `H3X_CLIENT_PROCESSED_PATH`.
+    pub fn env(mut self) -> Result<Self, String> {
+        self.remote_addr = env_parse("H3X_REMOTE_ADDR")?.or(self.remote_addr);
+        self.server_name = env_parse("H3X_SERVER_NAME")?.or(self.server_name);
+        self.ca_path = env_parse("H3X_CA_PATH")?.or(self.ca_path);
+        self.cert_path = env_parse("H3X_CLIENT_CERT_PATH")?.or(self.cert_path);
+        self.key_path = env_parse("H3X_CLIENT_KEY_PATH")?.or(self.key_path);
+        self.group = env_parse("H3X_CLIENT_GROUP")?.or(self.group);
+        self.processed_path = env_parse("H3X_CLIENT_PROCESSED_PATH")?.or(self.processed_path);
+        Ok(self)
+    }
+
     pub fn build(self) -> Result<ClientParams, String> {
         if self.namespaces.is_empty() {
             return Err("At least one namespace is required".into());
         }
+        if self.cert_path.is_some() != self.key_path.is_some() {
+            return Err("Client certificate and key must be set together".into());
+        }
 
         let token = self.token.ok_or("Token must be provided")?;
-        Ok(ClientParams::new(format!("client_id:{}", self.client_id), self.namespaces, token))
+        let mut params = ClientParams::new(format!("client_id:{}", self.client_id), self.namespaces, token);
+        params.publish = self.publish;
+        params.group = self.group;
+        params.processed_path = self.processed_path;
+        if let Some(handler) = self.handler {
+            params.handler = handler;
+        }
+        if let Some(addr) = self.remote_addr {
+            params.remote_addr = addr;
+        }
+        if let Some(name) = self.server_name {
+            params.server_name = name;
+        }
+        if let Some(path) = self.ca_path {
+            params.ca_path = path;
+        }
+        params.cert_path = self.cert_path;
+        params.key_path = self.key_path;
+        Ok(params)
     }
 }
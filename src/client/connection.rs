@@ -1,28 +1,83 @@
 use anyhow::{bail, Result};
-use quinn::Connection;
+use quinn::{Connection, RecvStream, SendStream};
 use crate::{client::event::handle_event_frame, protocol::h3x as pb};
+use crate::client::event::{dispatch_batch, replay_events};
+use crate::client::send::subscribe;
+use crate::client::handler::EventHandler;
+use crate::client::processed::ProcessedStore;
 use crate::protocol::h3x::Frame;
 use std::convert::TryFrom;
 use crate::protocol::h3x::{frame, FrameType};
+use crate::protocol::version::{self, Negotiated, PROTO_VERSION};
 
+/// Agree on a protocol version and capabilities with the server. Must be the
+/// first exchange on a connection, before `authenticate`.
+pub async fn hello(conn: &Connection) -> Result<Negotiated> {
+    let (mut send, mut recv) = conn.open_bi().await?;
+    let frame = pb::Frame {
+        version: PROTO_VERSION,
+        stream_id: 0,
+        r#type: pb::FrameType::Hello as i32,
+        payload: Some(pb::frame::Payload::Hello(version::hello())),
+    };
+    frame.write_to(&mut send).await?;
 
-const PROTO_VERSION: u32 = 1;
+    match Frame::read_from(&mut recv).await? {
+        Some(reply) => match (pb::FrameType::try_from(reply.r#type), reply.payload) {
+            (Ok(pb::FrameType::HelloAck), Some(pb::frame::Payload::HelloAck(ack))) => {
+                if !version::SUPPORTED_VERSIONS.contains(&ack.version) {
+                    bail!("❌ Server chose unsupported protocol version {}", ack.version);
+                }
+                let negotiated = Negotiated::from(&ack);
+                println!("👋 Protocol v{} with {:?}", negotiated.version, negotiated.capabilities);
+                Ok(negotiated)
+            }
+            (Ok(pb::FrameType::Error), Some(pb::frame::Payload::Error(error))) => {
+                bail!("❌ Hello rejected by server: {error} (server speaks {:?})", error.supported_versions)
+            }
+            (Ok(other), payload) => bail!("❌ Unexpected frame during hello: {:?} {:?}", other, payload),
+            (Err(bad), _) => bail!("❌ Unknown FrameType value: {}", bad),
+        },
+        None => bail!("❌ No response received after hello"),
+    }
+}
 
-pub async fn authenticate(conn: &Connection, client_id: String, token: String, namespaces: Vec<String>) -> Result<()> {
+/// Read the next frame from the server, failing if its envelope version is
+/// not the one negotiated in Hello.
+pub async fn read_frame(recv: &mut RecvStream, protocol: &Negotiated) -> Result<Option<Frame>> {
+    let frame = Frame::read_from(recv).await?;
+    if let Some(frame) = &frame
+        && frame.version != protocol.version
+    {
+        bail!("❌ Server sent protocol version {} on a v{} connection", frame.version, protocol.version);
+    }
+    Ok(frame)
+}
+
+pub async fn authenticate(
+    conn: &Connection,
+    protocol: &Negotiated,
+    client_id: String,
+    token: String,
+    namespaces: Vec<String>,
+) -> Result<()> {
     let auth = pb::Auth { client_id, token, namespaces };
 
     let (mut send, mut recv) = conn.open_bi().await?;
     let frame = pb::Frame {
-        version: 1,
+        version: PROTO_VERSION,
         stream_id: 0,
         r#type: pb::FrameType::Auth as i32,
         payload: Some(pb::frame::Payload::Auth(auth)),
     };
     frame.write_to(&mut send).await?;
 
-    match Frame::read_from(&mut recv).await? {
+    match read_frame(&mut recv, protocol).await? {
         Some(reply) => match (pb::FrameType::try_from(reply.r#type), reply.payload) {
             (Ok(pb::FrameType::AuthAck),   _) => Ok(()),
+            (Ok(pb::FrameType::AuthError), Some(pb::frame::Payload::Error(error))) => {
+                bail!("❌ Auth rejected by server: {error}")
+            }
             (Ok(pb::FrameType::AuthError), _) => bail!("❌ Auth rejected by server"),
             (Ok(other), payload) => bail!("❌ Unexpected frame during auth: {:?} {:?}", other, payload),
             (Err(bad), _) => bail!("❌ Unknown FrameType value: {}", bad),
@@ -31,23 +86,73 @@ pub async fn authenticate(conn: &Connection, client_id: String, token: String, n
     }
 }
 
-pub async fn receive_loop(conn: &Connection, namespaces: Vec<String>) -> Result<()> {
-    // Open a BI stream to request & receive events
+/// Subscribe to pushed events and handle them until the server closes the
+/// stream, replaying the stored backlog alongside.
+/// `group` is the consumer group to join; empty means the server's default.
+/// `processed`, if set, filters events this client already handled.
+pub async fn receive_loop(
+    conn: &Connection,
+    protocol: &Negotiated,
+    namespaces: Vec<String>,
+    group: String,
+    handler: &dyn EventHandler,
+    processed: Option<&ProcessedStore>,
+) -> Result<()> {
+    // Open a BI stream to receive pushed events
     let (mut send, mut recv) = conn.open_bi().await?;
 
-    // Ask server for events (you can set `limit` if you support it)
-    let fetch = pb::FetchEvents { namespaces, limit: 0 };
-    let fetch_frame = pb::Frame {
-        version: PROTO_VERSION,
-        stream_id: 1,
-        r#type: FrameType::FetchEvents as i32,
-        payload: Some(frame::Payload::FetchEvents(fetch)),
+    if let Err(e) = subscribe(1, namespaces.clone(), group.clone(), &mut send).await {
+        bail!("❌ Failed to send Subscribe: {e}");
+    }
+
+    // Server acks once the subscription is live; anything enqueued later is pushed.
+    let Some(reply) = read_frame(&mut recv, protocol).await? else {
+        bail!("❌ Server closed stream before confirming Subscribe");
+    };
+    let reason = match reply.payload {
+        Some(frame::Payload::Error(error)) => error.to_string(),
+        _ => String::from("no details"),
+    };
+    let subscribed = match FrameType::try_from(reply.r#type) {
+        Ok(FrameType::Ack) => {
+            println!("📡 Subscribed to {:?}", namespaces);
+            true
+        }
+        // Publish-only clients hold no read grant; keep the connection for publishing.
+        Ok(FrameType::AuthError) => {
+            eprintln!("⚠️ Not subscribed to {:?}: no read access ({reason})", namespaces);
+            false
+        }
+        Ok(FrameType::Error) => {
+            eprintln!("⚠️ Not subscribed to {:?}: {reason}", namespaces);
+            false
+        }
+        other => bail!("❌ Unexpected reply to Subscribe: {:?}", other),
     };
-    fetch_frame.write_to(&mut send).await?;
 
-    // Read frames until stream closes or an error occurs
+    // Catch up on what was stored before subscribing while handling pushes,
+    // so pushed events are acked before their leases run out.
+    let replay = async {
+        if subscribed {
+            replay_events(conn, protocol, namespaces, group, handler, processed).await?;
+        }
+        Ok(())
+    };
+    tokio::try_join!(replay, read_pushed(&mut send, &mut recv, protocol, handler, processed))?;
+    Ok(())
+}
+
+/// Handle pushed events and the server's replies on a subscription stream
+/// until the server closes it.
+async fn read_pushed(
+    send: &mut SendStream,
+    recv: &mut RecvStream,
+    protocol: &Negotiated,
+    handler: &dyn EventHandler,
+    processed: Option<&ProcessedStore>,
+) -> Result<()> {
     loop {
-        match pb::Frame::read_from(&mut recv).await? {
+        match read_frame(recv, protocol).await? {
             None => {
                 println!("ℹ️ Server closed stream.");
                 break;
@@ -56,24 +161,28 @@ pub async fn receive_loop(conn: &Connection, namespaces: Vec<String>) -> Result<
                 match FrameType::try_from(incoming.r#type) {
                     Ok(FrameType::Event) => {
                         // Your per-event handler with ack+retry
-                        handle_event_frame(incoming, &mut send).await?;
+                        handle_event_frame(incoming, send, handler, processed).await?;
                     }
                     Ok(FrameType::EventsBatch) => {
-                        // Split the batch and reuse the same event handler
+                        // Handle every event, then ack the batch per namespace
                         if let Some(frame::Payload::EventsBatch(batch)) = incoming.payload {
-                            for ev in batch.events {
-                                let single = pb::Frame {
-                                    version: PROTO_VERSION,
-                                    stream_id: incoming.stream_id,
-                                    r#type: FrameType::Event as i32,
-                                    payload: Some(frame::Payload::Event(ev)),
-                                };
-                                handle_event_frame(single, &mut send).await?;
-                            }
+                            dispatch_batch(batch.events, incoming.stream_id, send, handler, processed).await;
                         } else {
                             eprintln!("❌ EventsBatch frame missing payload");
                         }
                     }
+                    Ok(FrameType::AckResult) => {
+                        if let Some(frame::Payload::AckResult(result)) = incoming.payload
+                            && !result.not_found.is_empty()
+                        {
+                            eprintln!("⚠️ Server had no pending {:?} in {}", result.not_found, result.namespace);
+                        }
+                    }
+                    Ok(FrameType::Error | FrameType::Nack | FrameType::AuthError) => {
+                        if let Some(frame::Payload::Error(error)) = incoming.payload {
+                            eprintln!("⚠️ Server error: {error}");
+                        }
+                    }
                     Ok(other) => {
                         eprintln!("ℹ️ Ignoring frame type: {:?}", other);
                     }
@@ -1,43 +1,28 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use quinn::{Connection, SendStream};
 use tokio::time::{sleep, Duration};
 
 use crate::protocol::h3x as pb;
-use crate::protocol::h3x::Frame;
-use crate::client::send::fetch_events;
-use super::send::ack_event;
+use crate::protocol::version::Negotiated;
+use crate::client::handler::{Disposition, EventHandler};
+use crate::client::connection::read_frame;
+use crate::client::processed::ProcessedStore;
+use crate::client::send::{fetch_events, replay};
+use super::send::{ack_event, ack_events, nack_event};
+use std::collections::{BTreeMap, HashMap};
+
+/// Events requested per FetchEvents while catching up.
+const REPLAY_PAGE_SIZE: usize = 100;
 
 pub async fn handle_event_frame(
     frame: pb::Frame,
     send: &mut SendStream,
+    handler: &dyn EventHandler,
+    processed: Option<&ProcessedStore>,
 ) -> Result<()> {
     match (pb::FrameType::try_from(frame.r#type), frame.payload) {
         (Ok(pb::FrameType::Event), Some(pb::frame::Payload::Event(event))) => {
-            println!("📥 Received Event [{}]: {}", event.namespace, event.r#type);
-
-            // Retry ack if it fails
-            let mut attempts = 0usize;
-            let max_retries = 5usize;
-            let mut delay = Duration::from_secs(1);
-
-            while attempts < max_retries {
-                match ack_event(frame.stream_id, event.namespace.clone(), event.id.clone(), send).await {
-                    Ok(_) => {
-                        println!("✅ Acked event {}", event.id);
-                        break;
-                    }
-                    Err(e) => {
-                        attempts += 1;
-                        eprintln!("❌ Failed to ack event (attempt {attempts}): {e}");
-                        sleep(delay).await;
-                        delay *= 2;
-                    }
-                }
-            }
-
-            if attempts == max_retries {
-                eprintln!("❌ Giving up ack after {} attempts for event {}", max_retries, event.id);
-            }
+            dispatch_event(&event, frame.stream_id, send, handler, processed).await;
         }
         // Not an Event frame; ignore or log as needed.
         (kind, _) => {
@@ -48,65 +33,291 @@ pub async fn handle_event_frame(
     Ok(())
 }
 
+/// Run the application handler on one event, then ack, nack or leave it
+/// pending as the handler decided. Ack/Nack writes are retried with backoff.
+/// With a `processed` store, events it already holds are acked without
+/// running the handler, and handled events are recorded before their ack.
+async fn dispatch_event(
+    event: &pb::Event,
+    stream_id: u32,
+    send: &mut SendStream,
+    handler: &dyn EventHandler,
+    processed: Option<&ProcessedStore>,
+) {
+    if processed.is_some_and(|store| store.contains(event)) {
+        println!("♻️ Event {} already processed, acking redelivery", event.id);
+        settle(&event.namespace, &Settlement::Ack(vec![event.id.clone()]), stream_id, send).await;
+        return;
+    }
+
+    let disposition = handler.handle(event).await;
+    let settlement = match disposition {
+        Disposition::Pending => {
+            println!("⏸️ Leaving event {} pending", event.id);
+            return;
+        }
+        Disposition::Ack => {
+            if let Some(store) = processed {
+                remember(store, std::slice::from_ref(event)).await;
+            }
+            Settlement::Ack(vec![event.id.clone()])
+        }
+        Disposition::Nack(reason) => Settlement::Nack(event.id.clone(), reason, Duration::ZERO),
+        Disposition::Requeue(reason, delay) => Settlement::Nack(event.id.clone(), reason, delay),
+    };
+
+    settle(&event.namespace, &settlement, stream_id, send).await;
+}
+
+/// Run the handler on every event of a batch. Nacks go out as they happen;
+/// acks are collected and sent as one AckEvent per namespace at the end.
+/// With a `processed` store, see `dispatch_event`; the batch's handled events
+/// are recorded together before any of them is acked.
+pub async fn dispatch_batch(
+    events: Vec<pb::Event>,
+    stream_id: u32,
+    send: &mut SendStream,
+    handler: &dyn EventHandler,
+    processed: Option<&ProcessedStore>,
+) {
+    let mut acks: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut handled = Vec::new();
+
+    for event in events {
+        if processed.is_some_and(|store| store.contains(&event)) {
+            println!("♻️ Event {} already processed, acking redelivery", event.id);
+            acks.entry(event.namespace).or_default().push(event.id);
+            continue;
+        }
+
+        match handler.handle(&event).await {
+            Disposition::Ack => {
+                acks.entry(event.namespace.clone()).or_default().push(event.id.clone());
+                handled.push(event);
+            }
+            Disposition::Pending => println!("⏸️ Leaving event {} pending", event.id),
+            Disposition::Nack(reason) => {
+                let settlement = Settlement::Nack(event.id, reason, Duration::ZERO);
+                settle(&event.namespace, &settlement, stream_id, send).await;
+            }
+            Disposition::Requeue(reason, delay) => {
+                let settlement = Settlement::Nack(event.id, reason, delay);
+                settle(&event.namespace, &settlement, stream_id, send).await;
+            }
+        }
+    }
+
+    if let Some(store) = processed {
+        remember(store, &handled).await;
+    }
+    for (namespace, ids) in acks {
+        settle(&namespace, &Settlement::Ack(ids), stream_id, send).await;
+    }
+}
+
+/// Record handled events and make them durable before they are acked. A
+/// failure is logged and the ack still goes out: the server would otherwise
+/// redeliver, and the handler has already run.
+async fn remember(store: &ProcessedStore, events: &[pb::Event]) {
+    if events.is_empty() {
+        return;
+    }
+    let recorded = events.iter().try_for_each(|event| store.record(event));
+    if let Err(e) = recorded {
+        eprintln!("⚠️ Failed to record processed events: {e:#}");
+    } else if let Err(e) = store.flush().await {
+        eprintln!("⚠️ Failed to flush processed store: {e:#}");
+    }
+}
+
+/// What the client reports back for events of one namespace.
+#[derive(Debug)]
+enum Settlement {
+    Ack(Vec<String>),
+    /// Event id, reason, requeue delay.
+    Nack(String, String, Duration),
+}
+
+impl std::fmt::Display for Settlement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Settlement::Ack(ids) if ids.len() == 1 => write!(f, "Ack event {}", ids[0]),
+            Settlement::Ack(ids) => write!(f, "Ack {} events", ids.len()),
+            Settlement::Nack(id, _, delay) if delay.is_zero() => write!(f, "Nack event {id}"),
+            Settlement::Nack(id, _, delay) => write!(f, "Requeue event {id} after {delay:?}"),
+        }
+    }
+}
+
+/// Send one settlement, retrying up to 5 times with exponential backoff.
+async fn settle(namespace: &str, settlement: &Settlement, stream_id: u32, send: &mut SendStream) {
+    let mut attempts = 0usize;
+    let max_retries = 5usize;
+    let mut delay = Duration::from_secs(1);
+
+    while attempts < max_retries {
+        let sent = match settlement {
+            Settlement::Ack(ids) if ids.len() == 1 => {
+                ack_event(stream_id, namespace.to_string(), ids[0].clone(), send).await
+            }
+            Settlement::Ack(ids) => ack_events(stream_id, namespace.to_string(), ids.clone(), send).await,
+            Settlement::Nack(id, reason, requeue_delay) => {
+                nack_event(stream_id, namespace.to_string(), id.clone(), reason.clone(), *requeue_delay, send).await
+            }
+        };
+
+        match sent {
+            Ok(_) => {
+                println!("✅ {} in {}", settlement, namespace);
+                break;
+            }
+            Err(e) => {
+                attempts += 1;
+                eprintln!("❌ Failed to {} (attempt {attempts}): {e}", settlement);
+                sleep(delay).await;
+                delay *= 2;
+            }
+        }
+    }
+
+    if attempts == max_retries {
+        eprintln!("❌ Giving up on {} after {} attempts in {}", settlement, max_retries, namespace);
+    }
+}
+
+/// Fetch and handle everything stored for `namespaces`, one page per stream,
+/// following the server's cursors until it reports nothing more.
 pub async fn replay_events(
     conn: &Connection,
+    protocol: &Negotiated,
     namespaces: Vec<String>,
+    group: String,
+    handler: &dyn EventHandler,
+    processed: Option<&ProcessedStore>,
 ) -> Result<()> {
+    let mut cursors = HashMap::new();
+
+    loop {
+        let Some(batch) = replay_page(conn, protocol, namespaces.clone(), cursors, group.clone(), handler, processed).await? else {
+            return Ok(());
+        };
+        if !batch.has_more {
+            return Ok(());
+        }
+        cursors = batch.next_cursors;
+    }
+}
+
+/// One FetchEvents round trip. Returns the batch (events already handled),
+/// or `None` if the server answered with something else.
+async fn replay_page(
+    conn: &Connection,
+    protocol: &Negotiated,
+    namespaces: Vec<String>,
+    cursors: HashMap<String, String>,
+    group: String,
+    handler: &dyn EventHandler,
+    processed: Option<&ProcessedStore>,
+) -> Result<Option<pb::EventsBatch>> {
     // Open bidirectional stream to request replay
     let (mut send, mut recv) = conn.open_bi().await?;
 
     // Send FetchEvents request (your helper should build a pb::Frame internally)
     let stream_id: u32 = send.id().index().try_into().unwrap_or(0);
-    
-    if let Err(e) = fetch_events(stream_id, namespaces.clone(), 100, &mut send).await {
+
+    if let Err(e) = fetch_events(stream_id, namespaces, REPLAY_PAGE_SIZE, cursors, group, &mut send).await {
         eprintln!("❌ Failed to send FetchEvents request: {e}");
     }
 
-    // Expect an EventsBatch in response
-    let response = match Frame::read_from(&mut recv).await? {
-        Some(resp) => resp,
-        None => {
-            eprintln!("❌ Unexpected EOF waiting for EventsBatch response");
-            return Ok(());
+    // Expect an EventsBatch in response, possibly after Errors for single namespaces
+    let response = loop {
+        match read_frame(&mut recv, protocol).await? {
+            Some(resp) => match server_error(&resp) {
+                Some(error) => eprintln!("⚠️ FetchEvents: {error}"),
+                None => break resp,
+            },
+            None => {
+                eprintln!("❌ Unexpected EOF waiting for EventsBatch response");
+                return Ok(None);
+            }
         }
     };
 
     let (kind, payload) = (pb::FrameType::try_from(response.r#type), response.payload);
-    let batch = match (kind, payload) {
+    let mut batch = match (kind, payload) {
         (Ok(pb::FrameType::EventsBatch), Some(pb::frame::Payload::EventsBatch(batch))) => batch,
         other => {
             eprintln!("❌ Unexpected or missing EventsBatch response: {:?}", other);
-            return Ok(());
+            return Ok(None);
         }
     };
 
     println!("🔁 Replaying {} persisted events", batch.events.len());
 
-    for event in batch.events {
+    for event in &batch.events {
         println!("📥 Replaying Event [{}]: {}", event.namespace, event.r#type);
+    }
+    let events = std::mem::take(&mut batch.events);
+    dispatch_batch(events, response.stream_id, &mut send, handler, processed).await;
+
+    if let Err(e) = send.finish().await {
+        eprintln!("❌ Failed to finish stream after replay: {e}");
+    }
+
+    Ok(Some(batch))
+}
+
+/// The Error carried by an Error frame, if `frame` is one.
+fn server_error(frame: &pb::Frame) -> Option<&pb::Error> {
+    match (pb::FrameType::try_from(frame.r#type), &frame.payload) {
+        (Ok(pb::FrameType::Error), Some(pb::frame::Payload::Error(error))) => Some(error),
+        _ => None,
+    }
+}
 
-        // Acknowledge after processing
-        let mut attempts = 0usize;
-        let max_retries = 5usize;
-        let mut delay = Duration::from_secs(1);
-
-        while attempts < max_retries {
-            match ack_event(response.stream_id, event.namespace.clone(), event.id.clone(), &mut send).await {
-                Ok(_) => {
-                    println!("✅ Acked replayed event {}", event.id);
-                    break;
-                }
-                Err(e) => {
-                    attempts += 1;
-                    eprintln!("❌ Failed to ack replayed event (attempt {attempts}): {e}");
-                    sleep(delay).await;
-                    delay *= 2;
-                }
+/// Stream a namespace's retained history (see `pb::Replay`) through `handler`.
+/// Replayed events are not leased, so nothing is acked or nacked; the
+/// handler's dispositions are only logged. Returns how many events were seen.
+pub async fn replay_history(
+    conn: &Connection,
+    protocol: &Negotiated,
+    request: pb::Replay,
+    handler: &dyn EventHandler,
+) -> Result<usize> {
+    let (mut send, mut recv) = conn.open_bi().await?;
+    let stream_id: u32 = send.id().index().try_into().unwrap_or(0);
+    let namespace = request.namespace.clone();
+
+    if let Err(e) = replay(stream_id, request, &mut send).await {
+        bail!("Failed to send Replay request: {e}");
+    }
+
+    let mut seen = 0usize;
+    loop {
+        let Some(response) = read_frame(&mut recv, protocol).await? else {
+            bail!("Stream closed before the replay of {namespace} finished");
+        };
+        if let Some(error) = server_error(&response) {
+            eprintln!("⚠️ Replay: {error}");
+            continue;
+        }
+
+        let batch = match (pb::FrameType::try_from(response.r#type), response.payload) {
+            (Ok(pb::FrameType::EventsBatch), Some(pb::frame::Payload::EventsBatch(batch))) => batch,
+            other => bail!("Unexpected reply to Replay: {:?}", other),
+        };
+
+        for event in &batch.events {
+            println!("⏪ Replaying Event [{}] offset={}: {}", event.namespace, event.offset, event.r#type);
+            match handler.handle(event).await {
+                Disposition::Ack | Disposition::Pending => {}
+                other => eprintln!("⚠️ Handler returned {:?} for replayed event {}; ignored", other, event.id),
             }
         }
+        seen += batch.events.len();
 
-        if attempts == max_retries {
-            eprintln!("❌ Giving up on ack for replayed event {}", event.id);
+        if !batch.has_more {
+            break;
         }
     }
 
@@ -114,5 +325,6 @@ pub async fn replay_events(
         eprintln!("❌ Failed to finish stream after replay: {e}");
     }
 
-    Ok(())
+    println!("✅ Replayed {seen} event(s) from {namespace}");
+    Ok(seen)
 }
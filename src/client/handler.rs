@@ -0,0 +1,48 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+use crate::protocol::h3x::Event;
+
+/// What the client should do with a delivered event once the handler returns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Disposition {
+    /// Processed; ack it so the server deletes it.
+    Ack,
+    /// Failed; tell the server why. It is redelivered right away.
+    Nack(String),
+    /// Failed; tell the server why and redeliver it only after the delay.
+    Requeue(String, Duration),
+    /// Neither ack nor nack; the server will hand it out again later.
+    Pending,
+}
+
+pub type HandlerFuture<'a> = Pin<Box<dyn Future<Output = Disposition> + Send + 'a>>;
+
+/// Application callback run for every received event before it is acked.
+pub trait EventHandler: Send + Sync {
+    fn handle<'a>(&'a self, event: &'a Event) -> HandlerFuture<'a>;
+}
+
+/// Any `Fn(Event) -> impl Future<Output = Disposition>` closure is a handler.
+impl<F, Fut> EventHandler for F
+where
+    F: Fn(Event) -> Fut + Send + Sync,
+    Fut: Future<Output = Disposition> + Send + 'static,
+{
+    fn handle<'a>(&'a self, event: &'a Event) -> HandlerFuture<'a> {
+        Box::pin(self(event.clone()))
+    }
+}
+
+/// Default handler: log the event and ack it.
+pub struct PrintHandler;
+
+impl EventHandler for PrintHandler {
+    fn handle<'a>(&'a self, event: &'a Event) -> HandlerFuture<'a> {
+        Box::pin(async move {
+            println!("📥 Received Event [{}]: {}", event.namespace, event.r#type);
+            Disposition::Ack
+        })
+    }
+}
@@ -1,37 +1,41 @@
-mod ping;
+pub mod ping;
 pub mod params;
 pub mod builder;
 pub mod send;
 pub mod event;
+pub mod handler;
 pub mod connection;
+pub mod publisher;
+pub mod processed;
 
-use crate::client::connection::{authenticate, receive_loop};
-use crate::tls::generate_or_load_cert;
-use crate::client::event::replay_events;
+use crate::client::connection::{authenticate, hello, receive_loop};
+use crate::client::event::replay_history;
+use crate::protocol::h3x as pb;
+use crate::tls::{client_config, load_ca_roots, load_cert_chain, load_private_key};
 use crate::client::params::ClientParams;
-use crate::state::registry::{ClientMetadata};
+use crate::client::processed::ProcessedStore;
+use crate::client::publisher::{Outbox, Publisher};
 use tokio_util::sync::CancellationToken;
 
-use quinn::{ClientConfig, Connection, Endpoint};
-use rustls::{ClientConfig as RustlsClientConfig, RootCertStore};
+use quinn::{Connection, Endpoint};
 
-use std::sync::Arc;
-use std::collections::HashMap;
 use std::time::Duration;
+use tokio::task::JoinHandle;
 use tokio::time::sleep;
 
+/// Spawn the client loop and return a cloneable `Publisher` for it.
+pub fn start_client(params: ClientParams, cancel_token: CancellationToken) -> (Publisher, JoinHandle<()>) {
+    let (publisher, outbox) = publisher::channel(params.publish.clone());
+    let handle = tokio::spawn(run_client_with_outbox(params, cancel_token, outbox));
+    (publisher, handle)
+}
+
 pub async fn run_client(params: ClientParams, cancel_token: CancellationToken) {
-    let mut registry_map = HashMap::new();
-    for ns in &params.namespaces {
-        registry_map.insert(
-            ns.clone(),
-            ClientMetadata {
-                client_id: params.client_id(),
-                token: params.token.clone(),
-            },
-        );
-    }
+    let (_, outbox) = publisher::channel(params.publish.clone());
+    run_client_with_outbox(params, cancel_token, outbox).await
+}
 
+async fn run_client_with_outbox(params: ClientParams, cancel_token: CancellationToken, mut outbox: Outbox) {
     let mut endpoint = match Endpoint::client("0.0.0.0:0".parse().unwrap()) {
         Ok(ep) => ep,
         Err(e) => {
@@ -40,7 +44,21 @@ pub async fn run_client(params: ClientParams, cancel_token: CancellationToken) {
         }
     };
 
-    endpoint.set_default_client_config(build_client_config());
+    match build_client_config(&params) {
+        Ok(config) => endpoint.set_default_client_config(config),
+        Err(e) => {
+            eprintln!("❌ TLS setup failed: {e:#}");
+            return;
+        }
+    }
+
+    let processed = match params.processed_path.as_ref().map(ProcessedStore::open).transpose() {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("❌ {e:#}");
+            return;
+        }
+    };
 
     loop {
         tokio::select! {
@@ -49,22 +67,43 @@ pub async fn run_client(params: ClientParams, cancel_token: CancellationToken) {
                 break;
             }
             _ = async {
-                match connect_to_server(&endpoint).await {
+                match connect_to_server(&endpoint, &params).await {
                     Ok(conn) => {
                         println!("🤝 Connected to server.");
 
-                        if let Err(e) = authenticate(&conn, params.client_id(), params.token(), params.namespaces()).await {
+                        let protocol = match hello(&conn).await {
+                            Ok(protocol) => protocol,
+                            Err(e) => {
+                                eprintln!("❌ Protocol negotiation failed: {e}");
+                                return;
+                            }
+                        };
+
+                        if let Err(e) = authenticate(&conn, &protocol, params.client_id(), params.token(), params.namespaces()).await {
                             eprintln!("❌ Authentication failed: {e}");
                             return;
                         }
 
-                        // Open one BI stream to fetch+receive events
-                        if let Err(e) = receive_loop(&conn, params.namespaces().to_vec()).await {
-                            eprintln!("❌ Receive loop ended: {e}");
-                        }
-
-                        if let Err(e) = replay_events(&conn, params.namespaces().to_vec()).await {
-                            eprintln!("❌ Replay events failed: {e}");
+                        // Publishing runs on its own stream alongside fetch+receive.
+                        tokio::select! {
+                            // Subscribe, replay the backlog, then receive pushed events
+                            res = receive_loop(
+                                &conn,
+                                &protocol,
+                                params.namespaces().to_vec(),
+                                params.group.clone().unwrap_or_default(),
+                                params.handler.as_ref(),
+                                processed.as_ref(),
+                            ) => {
+                                if let Err(e) = res {
+                                    eprintln!("❌ Receive loop ended: {e}");
+                                }
+                            }
+                            res = outbox.run(&conn, &protocol) => {
+                                if let Err(e) = res {
+                                    eprintln!("❌ Publish stream ended: {e}");
+                                }
+                            }
                         }
 
                         println!("🔁 Attempting reconnection...");
@@ -81,23 +120,33 @@ pub async fn run_client(params: ClientParams, cancel_token: CancellationToken) {
     println!("👋 Client shut down cleanly.");
 }
 
-fn build_client_config() -> ClientConfig {
-    let (cert_chain, _) = generate_or_load_cert();
-    let cert = &cert_chain[0];
+/// Connect once, stream the history `request` selects through the configured
+/// handler, then disconnect. Returns how many events were replayed.
+pub async fn replay(params: &ClientParams, request: pb::Replay) -> anyhow::Result<usize> {
+    let mut endpoint = Endpoint::client("0.0.0.0:0".parse().unwrap())?;
+    endpoint.set_default_client_config(build_client_config(params)?);
 
-    let mut roots = RootCertStore::empty();
-    roots.add(cert).unwrap();
+    let conn = connect_to_server(&endpoint, params).await?;
+    let protocol = hello(&conn).await?;
+    authenticate(&conn, &protocol, params.client_id(), params.token(), params.namespaces()).await?;
 
-    let client_crypto = RustlsClientConfig::builder()
-        .with_safe_defaults()
-        .with_root_certificates(roots)
-        .with_no_client_auth();
+    let replayed = replay_history(&conn, &protocol, request, params.handler.as_ref()).await;
+    conn.close(0u32.into(), b"replay done");
+    endpoint.wait_idle().await;
+    replayed
+}
 
-    ClientConfig::new(Arc::new(client_crypto))
+fn build_client_config(params: &ClientParams) -> anyhow::Result<quinn::ClientConfig> {
+    let roots = load_ca_roots(&params.ca_path)?;
+    let identity = match (&params.cert_path, &params.key_path) {
+        (Some(cert), Some(key)) => Some((load_cert_chain(cert)?, load_private_key(key)?)),
+        _ => None,
+    };
+    client_config(roots, identity)
 }
 
-async fn connect_to_server(endpoint: &Endpoint) -> Result<Connection, quinn::ConnectionError> {
-    let connecting = endpoint.connect("127.0.0.1:5000".parse().unwrap(), "localhost")
+async fn connect_to_server(endpoint: &Endpoint, params: &ClientParams) -> Result<Connection, quinn::ConnectionError> {
+    let connecting = endpoint.connect(params.remote_addr, &params.server_name)
         .map_err(|e| {
             eprintln!("❌ Failed to start connection: {e}");
             quinn::ConnectionError::LocallyClosed
@@ -1,13 +1,45 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::client::handler::{EventHandler, PrintHandler};
+use crate::client::publisher::PublisherConfig;
+
 #[derive(Clone)]
 pub struct ClientParams {
     pub client_id: String,
     pub namespaces: Vec<String>,
     pub token: String,
+    /// Consumer group to receive events as; `None` joins the server's default group.
+    pub group: Option<String>,
+    pub publish: PublisherConfig,
+    pub handler: Arc<dyn EventHandler>,
+    /// Local store of handled event ids; set to skip redeliveries (exactly-once processing).
+    pub processed_path: Option<PathBuf>,
+    pub remote_addr: SocketAddr,
+    pub server_name: String,
+    pub ca_path: PathBuf,
+    /// Client certificate chain and key, presented when the server requires mTLS.
+    pub cert_path: Option<PathBuf>,
+    pub key_path: Option<PathBuf>,
 }
 
 impl ClientParams {
     pub fn new(client_id: String, namespaces: Vec<String>, token: String) -> Self {
-        Self { client_id, namespaces, token }
+        Self {
+            client_id,
+            namespaces,
+            token,
+            group: None,
+            publish: PublisherConfig::default(),
+            handler: Arc::new(PrintHandler),
+            processed_path: None,
+            remote_addr: "127.0.0.1:5000".parse().unwrap(),
+            server_name: "localhost".into(),
+            ca_path: "cert.der".into(),
+            cert_path: None,
+            key_path: None,
+        }
     }
 
     pub fn client_id(&self) -> String {
@@ -1,6 +1,7 @@
 use quinn::Connection;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::time::sleep;
+use std::collections::BTreeMap;
 use std::convert::TryFrom;
 
 use crate::protocol::h3x::{
@@ -11,8 +12,8 @@ use crate::protocol::h3x::{
     EventsBatch,
     AckEvent,
 };
+use crate::protocol::version::PROTO_VERSION;
 
-const PROTO_VERSION: u32 = 1;
 const CONTROL_STREAM_ID: u32 = 99;
 
 #[derive(Debug)]
@@ -74,24 +75,25 @@ pub async fn start_ping_loop(conn: Connection) -> Result<(), Shutdown> {
 
                         FrameType::EventsBatch => {
                             match frame.payload {
-                                Some(frame::Payload::EventsBatch(EventsBatch { events })) => {
+                                Some(frame::Payload::EventsBatch(EventsBatch { events, .. })) => {
                                     if !events.is_empty() {
                                         println!("🚚 Received {} event(s)", events.len());
                                     }
-                                    // Process events, then ack each one
+                                    // Process events, then ack them with one frame per namespace
+                                    let mut by_namespace: BTreeMap<String, Vec<String>> = BTreeMap::new();
                                     for ev in events {
-                                        let ack = AckEvent {
-                                            namespace: ev.namespace.clone(),
-                                            event_id: ev.id.clone(),
-                                        };
+                                        by_namespace.entry(ev.namespace).or_default().push(ev.id);
+                                    }
+                                    for (namespace, ids) in by_namespace {
+                                        let count = ids.len();
                                         let ack_frame = H3XFrame {
                                             version: PROTO_VERSION,
                                             stream_id: CONTROL_STREAM_ID,
                                             r#type: FrameType::AckEvent as i32,
-                                            payload: Some(frame::Payload::AckEvent(ack)),
+                                            payload: Some(frame::Payload::AckEvent(AckEvent::batch(namespace, ids))),
                                         };
                                         if let Err(e) = ack_frame.write_to(&mut send).await {
-                                            eprintln!("⚠️ Failed to send AckEvent for {}: {e}", ev.id);
+                                            eprintln!("⚠️ Failed to send AckEvent for {count} event(s): {e}");
                                         }
                                     }
                                 }
@@ -0,0 +1,133 @@
+// processed.rs
+// Local sled store of events the application already handled, so redeliveries
+// (after a lost ack, a reconnect or an expired lease) are acked without
+// reaching the handler again. Together with acking right after recording, this
+// gives exactly-once processing on top of the server's at-least-once delivery.
+//
+// Layout: one tree per namespace, event_id -> processed at (unix ms u64 BE) + offset (u64 BE).
+
+use anyhow::{Context, Result};
+use sled::Db;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::protocol::h3x::Event;
+use crate::utils::now_ms;
+
+/// How long processed ids are remembered by default.
+pub const DEFAULT_PROCESSED_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Handle to the local processed-event store. Cheap to clone.
+#[derive(Clone)]
+pub struct ProcessedStore {
+    db: Db,
+    max_age: Duration,
+}
+
+impl ProcessedStore {
+    /// Open (or create) the store at `path` and forget ids older than the
+    /// default max age. sled allows one process per store.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_with_max_age(path, DEFAULT_PROCESSED_MAX_AGE)
+    }
+
+    /// Like `open`, remembering processed ids for `max_age`. Redeliveries
+    /// older than that reach the handler again.
+    pub fn open_with_max_age<P: AsRef<Path>>(path: P, max_age: Duration) -> Result<Self> {
+        let path = path.as_ref();
+        let db = sled::open(path).with_context(|| format!("Failed to open processed store {}", path.display()))?;
+        let store = Self { db, max_age };
+
+        let pruned = store.prune()?;
+        if pruned > 0 {
+            println!("🧹 Forgot {pruned} processed event id(s) older than {max_age:?}");
+        }
+        Ok(store)
+    }
+
+    /// Whether `event` was already handled and acked by this client.
+    /// Read errors are logged and count as not processed.
+    pub fn contains(&self, event: &Event) -> bool {
+        match self.db.open_tree(&event.namespace).and_then(|tree| tree.contains_key(event.id.as_bytes())) {
+            Ok(found) => found,
+            Err(e) => {
+                eprintln!("⚠️ Processed store lookup failed for {}: {e}", event.id);
+                false
+            }
+        }
+    }
+
+    /// Remember `event` as handled. Call `flush` before acking it.
+    pub fn record(&self, event: &Event) -> Result<()> {
+        let mut value = now_ms().to_be_bytes().to_vec();
+        value.extend_from_slice(&event.offset.to_be_bytes());
+        self.db.open_tree(&event.namespace)?.insert(event.id.as_bytes(), value)?;
+        Ok(())
+    }
+
+    /// Make every recorded id durable.
+    pub async fn flush(&self) -> Result<()> {
+        self.db.flush_async().await?;
+        Ok(())
+    }
+
+    /// Forget ids recorded longer than the max age ago. Returns how many.
+    pub fn prune(&self) -> Result<usize> {
+        let cutoff = now_ms().saturating_sub(self.max_age.as_millis() as u64);
+        let mut pruned = 0;
+
+        for name in self.db.tree_names() {
+            if name == b"__sled__default" {
+                continue;
+            }
+            let tree = self.db.open_tree(&name)?;
+            for res in tree.iter() {
+                let (id, value) = res?;
+                let processed_at = value.first_chunk::<8>().map_or(0, |at| u64::from_be_bytes(*at));
+                if processed_at < cutoff {
+                    tree.remove(id)?;
+                    pruned += 1;
+                }
+            }
+        }
+
+        Ok(pruned)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn event(namespace: &str, id: &str) -> Event {
+        Event { id: id.into(), namespace: namespace.into(), ..Event::default() }
+    }
+
+    #[test]
+    fn remembers_processed_events_until_they_age_out() {
+        let dir = std::env::temp_dir().join(format!("h3x-processed-test-{}", Uuid::new_v4()));
+        let seen = event("orders", "e1");
+
+        {
+            let store = ProcessedStore::open(&dir).unwrap();
+            assert!(!store.contains(&seen));
+            store.record(&seen).unwrap();
+            assert!(store.contains(&seen), "redeliveries are skipped");
+            assert!(!store.contains(&event("billing", "e1")), "ids are per namespace");
+            assert_eq!(store.prune().unwrap(), 0);
+        }
+
+        std::thread::sleep(Duration::from_millis(100));
+        let store = ProcessedStore::open_with_max_age(&dir, Duration::from_millis(50)).unwrap();
+        assert!(!store.contains(&seen), "pruned on open");
+        store.record(&seen).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(store.prune().unwrap(), 1);
+        assert!(!store.contains(&seen));
+
+        drop(store);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,265 @@
+use anyhow::{anyhow, bail, Result};
+use chrono::Utc;
+use quinn::{Connection, RecvStream, SendStream};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{sleep_until, timeout, Instant};
+use uuid::Uuid;
+
+use crate::protocol::h3x::{
+    frame,
+    Capability,
+    Event,
+    EventsBatch,
+    Frame as H3XFrame,
+    FrameType,
+};
+use crate::client::connection::read_frame;
+use crate::protocol::version::{Negotiated, PROTO_VERSION};
+
+
+/// How the publisher groups events into frames.
+#[derive(Debug, Clone)]
+pub struct PublisherConfig {
+    /// Flush once this many events are buffered.
+    pub max_batch: usize,
+    /// Flush a non-empty buffer after this long, even if it is not full.
+    pub linger: Duration,
+    /// Fail a publish the server has not acked or rejected after this long,
+    /// e.g. when it was rejected by a reply the client cannot match to it.
+    pub ack_timeout: Duration,
+}
+
+impl Default for PublisherConfig {
+    fn default() -> Self {
+        Self {
+            max_batch: 100,
+            linger: Duration::from_millis(10),
+            ack_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+struct Outgoing {
+    event: Event,
+    /// Resolved with the stored event's id, or the rejection reason.
+    done: oneshot::Sender<Result<String, String>>,
+}
+
+/// Sent but not yet acked, keyed by event id. Survives reconnects so
+/// unacked events are re-sent on the next connection.
+type PendingAcks = Arc<Mutex<HashMap<String, Outgoing>>>;
+
+/// Cloneable handle for publishing events through a running client.
+#[derive(Clone)]
+pub struct Publisher {
+    tx: mpsc::Sender<Outgoing>,
+    pending: PendingAcks,
+    ack_timeout: Duration,
+}
+
+/// The receiving half of a `Publisher`, driven by the client's connection loop.
+pub struct Outbox {
+    rx: mpsc::Receiver<Outgoing>,
+    pending: PendingAcks,
+    config: PublisherConfig,
+}
+
+pub fn channel(config: PublisherConfig) -> (Publisher, Outbox) {
+    let (tx, rx) = mpsc::channel(config.max_batch.max(1) * 4);
+    let pending: PendingAcks = Arc::new(Mutex::new(HashMap::new()));
+    let publisher = Publisher {
+        tx,
+        pending: pending.clone(),
+        ack_timeout: config.ack_timeout,
+    };
+    (publisher, Outbox { rx, pending, config })
+}
+
+impl Publisher {
+    /// Build an Event with a fresh UUID and the current timestamp, publish it,
+    /// and wait for the server's AckEvent. Returns the event id.
+    pub async fn publish<N, T, M>(&self, namespace: N, event_type: T, message: M, data: Vec<u8>) -> Result<String>
+    where
+        N: Into<String>,
+        T: Into<String>,
+        M: Into<String>,
+    {
+        self.publish_event(Event {
+            namespace: namespace.into(),
+            r#type: event_type.into(),
+            message: message.into(),
+            data,
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// Publish a prepared Event. An empty `id` gets a UUID and a zero
+    /// `timestamp` gets the current time. Resolves once the server acks it,
+    /// with the id of the event the server already had if it deduplicated this one.
+    /// Fails when the server rejects it or does not ack it within `ack_timeout`;
+    /// a timed-out event is not re-sent after a reconnect.
+    pub async fn publish_event(&self, mut event: Event) -> Result<String> {
+        if event.namespace.is_empty() {
+            bail!("Event namespace must be set");
+        }
+        if event.id.is_empty() {
+            event.id = Uuid::new_v4().to_string();
+        }
+        if event.timestamp == 0 {
+            event.timestamp = Utc::now().timestamp();
+        }
+
+        let id = event.id.clone();
+        let (done, acked) = oneshot::channel();
+        self.tx
+            .send(Outgoing { event, done })
+            .await
+            .map_err(|_| anyhow!("Client is shut down"))?;
+
+        match timeout(self.ack_timeout, acked).await {
+            Ok(Ok(Ok(stored))) => Ok(stored),
+            Ok(Ok(Err(reason))) => bail!("Publish of {id} rejected: {reason}"),
+            Ok(Err(_)) => bail!("Client shut down before {id} was acked"),
+            Err(_) => {
+                self.pending.lock().unwrap().remove(&id);
+                bail!("Publish of {id} not acked within {:?}", self.ack_timeout)
+            }
+        }
+    }
+}
+
+impl Outbox {
+    /// Publish over `conn` until the connection fails. Events left unacked by
+    /// an earlier connection are re-sent first. Without the negotiated
+    /// batching capability every event goes out in its own Event frame.
+    pub async fn run(&mut self, conn: &Connection, protocol: &Negotiated) -> Result<()> {
+        let batching = protocol.allows(Capability::Batching);
+        let (mut send, recv) = conn.open_bi().await?;
+        let stream_id: u32 = send.id().index().try_into().unwrap_or(0);
+
+        let resend: Vec<Event> = self
+            .pending
+            .lock()
+            .unwrap()
+            .values()
+            .map(|p| p.event.clone())
+            .collect();
+        if !resend.is_empty() {
+            println!("🔁 Re-sending {} unacked event(s)", resend.len());
+            write_events(&mut send, stream_id, resend, batching).await?;
+        }
+
+        let pending = self.pending.clone();
+        tokio::select! {
+            res = self.write_loop(&mut send, stream_id, batching) => res,
+            res = read_acks(recv, pending, protocol) => res,
+        }
+    }
+
+    async fn write_loop(&mut self, send: &mut SendStream, stream_id: u32, batching: bool) -> Result<()> {
+        loop {
+            // Wait for the first event, then gather more until full or lingered.
+            let Some(first) = self.rx.recv().await else {
+                // Every Publisher is gone; keep the stream open for outstanding acks.
+                return std::future::pending().await;
+            };
+
+            let deadline = Instant::now() + self.config.linger;
+            let mut batch = vec![first];
+            while batch.len() < self.config.max_batch {
+                tokio::select! {
+                    next = self.rx.recv() => match next {
+                        Some(out) => batch.push(out),
+                        None => break,
+                    },
+                    _ = sleep_until(deadline) => break,
+                }
+            }
+
+            let events: Vec<Event> = {
+                let mut pending = self.pending.lock().unwrap();
+                batch
+                    .into_iter()
+                    // Publishes that already timed out are dropped, not sent late.
+                    .filter(|out| !out.done.is_closed())
+                    .map(|out| {
+                        let ev = out.event.clone();
+                        pending.insert(ev.id.clone(), out);
+                        ev
+                    })
+                    .collect()
+            };
+            if events.is_empty() {
+                continue;
+            }
+
+            write_events(send, stream_id, events, batching).await?;
+        }
+    }
+}
+
+async fn write_events(send: &mut SendStream, stream_id: u32, mut events: Vec<Event>, batching: bool) -> Result<()> {
+    if !batching {
+        for event in events {
+            write_frame(send, stream_id, FrameType::Event, frame::Payload::Event(event)).await?;
+        }
+        return Ok(());
+    }
+
+    let (r#type, payload) = if events.len() == 1 {
+        (FrameType::Event, frame::Payload::Event(events.remove(0)))
+    } else {
+        (FrameType::EventsBatch, frame::Payload::EventsBatch(EventsBatch { events, ..Default::default() }))
+    };
+    write_frame(send, stream_id, r#type, payload).await
+}
+
+async fn write_frame(send: &mut SendStream, stream_id: u32, r#type: FrameType, payload: frame::Payload) -> Result<()> {
+    let frame = H3XFrame {
+        version: PROTO_VERSION,
+        stream_id,
+        r#type: r#type as i32,
+        payload: Some(payload),
+    };
+    frame.write_to(send).await?;
+    Ok(())
+}
+
+async fn read_acks(mut recv: RecvStream, pending: PendingAcks, protocol: &Negotiated) -> Result<()> {
+    loop {
+        let Some(incoming) = read_frame(&mut recv, protocol).await? else {
+            bail!("Server closed publish stream");
+        };
+
+        match (FrameType::try_from(incoming.r#type), incoming.payload) {
+            (Ok(FrameType::AckEvent), Some(frame::Payload::AckEvent(ack))) => {
+                let mut pending = pending.lock().unwrap();
+                for id in ack.ids() {
+                    if let Some(out) = pending.remove(id) {
+                        let stored = ack.duplicates.get(id).map_or(id, String::as_str);
+                        let _ = out.done.send(Ok(stored.to_string()));
+                    }
+                }
+            }
+            // Failures about one event (e.g. it could not be stored) fail that publish.
+            (Ok(FrameType::Error | FrameType::Nack), Some(frame::Payload::Error(error))) => {
+                match pending.lock().unwrap().remove(&error.request_id) {
+                    Some(out) => {
+                        let _ = out.done.send(Err(error.to_string()));
+                    }
+                    None => eprintln!("⚠️ Server rejected a published frame: {error}"),
+                }
+            }
+            (Ok(FrameType::Nack), _) => {
+                eprintln!("⚠️ Server rejected a published frame");
+            }
+            (kind, _) => {
+                eprintln!("ℹ️ Ignoring frame on publish stream: {:?}", kind);
+            }
+        }
+    }
+}
@@ -1,6 +1,9 @@
 use quinn::SendStream;
+use std::collections::HashMap;
+use std::time::Duration;
 
 use crate::protocol::h3x as pb;
+use crate::protocol::version::PROTO_VERSION;
 
 // ACK an event back to the server
 pub async fn ack_event(
@@ -10,12 +13,14 @@ pub async fn ack_event(
     send: &mut SendStream,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let frame = pb::Frame {
-        version: 1,
+        version: PROTO_VERSION,
         stream_id,
         r#type: pb::FrameType::AckEvent as i32,
         payload: Some(pb::frame::Payload::AckEvent(pb::AckEvent {
             namespace,
             event_id: event_id.to_string(), // proto expects string
+            event_ids: Vec::new(),
+            duplicates: Default::default(),
         })),
     };
 
@@ -23,25 +28,109 @@ pub async fn ack_event(
     Ok(())
 }
 
+// ACK several events of one namespace with a single frame
+pub async fn ack_events(
+    stream_id: u32,
+    namespace: String,
+    event_ids: Vec<String>,
+    send: &mut SendStream,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let frame = pb::Frame {
+        version: PROTO_VERSION,
+        stream_id,
+        r#type: pb::FrameType::AckEvent as i32,
+        payload: Some(pb::frame::Payload::AckEvent(pb::AckEvent::batch(namespace, event_ids))),
+    };
+
+    frame.write_to(send).await?;
+    Ok(())
+}
+
 // Request events for a namespace
+/// Request up to `max` events (0 = server default) for consumer `group`
+/// (empty = default), continuing after `cursors` from a previous `EventsBatch`.
 pub async fn fetch_events(
     stream_id: u32,
     namespaces: Vec<String>,
     max: usize,
+    cursors: HashMap<String, String>,
+    group: String,
     send: &mut SendStream,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let limit = u32::try_from(max).unwrap_or(u32::MAX);
 
     let frame = pb::Frame {
-        version: 1,
+        version: PROTO_VERSION,
         stream_id,
         r#type: pb::FrameType::FetchEvents as i32,
         payload: Some(pb::frame::Payload::FetchEvents(pb::FetchEvents {
             namespaces,
             limit,
+            cursors,
+            group,
         })),
     };
 
     frame.write_to(send).await?;
     Ok(())
 }
+
+// NACK an event back to the server; it is redelivered after `requeue_delay`
+pub async fn nack_event(
+    stream_id: u32,
+    namespace: String,
+    event_id: String,
+    reason: String,
+    requeue_delay: Duration,
+    send: &mut SendStream,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let frame = pb::Frame {
+        version: PROTO_VERSION,
+        stream_id,
+        r#type: pb::FrameType::Nack as i32,
+        payload: Some(pb::frame::Payload::Nack(pb::Nack {
+            namespace,
+            event_id,
+            reason,
+            requeue_delay_ms: u32::try_from(requeue_delay.as_millis()).unwrap_or(u32::MAX),
+        })),
+    };
+
+    frame.write_to(send).await?;
+    Ok(())
+}
+
+// Subscribe to events pushed as they are enqueued, as a member of `group`
+pub async fn subscribe(
+    stream_id: u32,
+    namespaces: Vec<String>,
+    group: String,
+    send: &mut SendStream,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let frame = pb::Frame {
+        version: PROTO_VERSION,
+        stream_id,
+        r#type: pb::FrameType::Subscribe as i32,
+        payload: Some(pb::frame::Payload::Subscribe(pb::Subscribe { namespaces, group })),
+    };
+
+    frame.write_to(send).await?;
+    Ok(())
+}
+
+// Ask for a namespace's retained history; answered with EventsBatch frames
+pub async fn replay(
+    stream_id: u32,
+    replay: pb::Replay,
+    send: &mut SendStream,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let frame = pb::Frame {
+        version: PROTO_VERSION,
+        stream_id,
+        r#type: pb::FrameType::Replay as i32,
+        payload: Some(pb::frame::Payload::Replay(replay)),
+    };
+
+    frame.write_to(send).await?;
+    Ok(())
+}
@@ -0,0 +1,115 @@
+// config.rs
+// Optional TOML file shared by server and client. Every key is optional;
+// builders apply file values first and `H3X_*` env vars on top.
+//
+// [server]
+// bind_addr = "0.0.0.0:5000"
+// data_dir  = "data"
+// cert_path = "cert.der"
+// key_path  = "key.der"
+// tls_dev   = false      # generate a self-signed cert if missing
+// client_ca_path = "clients-ca.pem"   # require client certs (mTLS)
+// registry_path  = "data/registry.json"
+// visibility_timeout_secs = 30       # redeliver unacked events after this
+// max_deliveries = 5                 # dead-letter after this many failures (default: unlimited)
+// retention_max_age_secs = 604800    # keep acked events, dropping them after 7 days
+// retention_max_bytes = 1073741824   #   ... or once a namespace holds 1 GiB
+// retention_max_count = 1000000      #   ... or beyond 1M events (default: delete once acked)
+// ttl_secs = 86400                   # expire events not delivered within a day (default: never)
+// dead_letter_expired = false        # dead-letter expired events instead of deleting them
+// dedupe_window_secs = 600           # ack re-published ids within 10 minutes without storing them again
+//
+// [client]
+// remote_addr = "127.0.0.1:5000"
+// server_name = "localhost"
+// ca_path     = "cert.der"
+// cert_path   = "client.pem"  # presented when the server requires mTLS
+// key_path    = "client.key"
+// group       = "billing"     # consumer group (default: "default")
+// processed_path = "data/processed"   # skip redeliveries of handled events (exactly-once)
+//
+// [namespaces.orders]   # per-namespace overrides of the server defaults
+// max_deliveries = 3
+// retention_max_count = 10000
+// ttl_secs = 3600
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use crate::state::policy::NamespacePolicy;
+
+/// Env var naming the TOML file to load, if any.
+pub const CONFIG_ENV: &str = "H3X_CONFIG";
+
+#[derive(Debug, Default, Deserialize)]
+pub struct FileConfig {
+    #[serde(default)]
+    pub server: ServerSection,
+    #[serde(default)]
+    pub client: ClientSection,
+    /// Per-namespace policy overrides, keyed by namespace.
+    #[serde(default)]
+    pub namespaces: HashMap<String, NamespacePolicy>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ServerSection {
+    pub bind_addr: Option<SocketAddr>,
+    pub data_dir: Option<PathBuf>,
+    pub cert_path: Option<PathBuf>,
+    pub key_path: Option<PathBuf>,
+    pub tls_dev: Option<bool>,
+    pub client_ca_path: Option<PathBuf>,
+    pub registry_path: Option<PathBuf>,
+    pub visibility_timeout_secs: Option<u64>,
+    pub max_deliveries: Option<u32>,
+    pub retention_max_age_secs: Option<u64>,
+    pub retention_max_bytes: Option<u64>,
+    pub retention_max_count: Option<u64>,
+    pub ttl_secs: Option<u64>,
+    pub dead_letter_expired: Option<bool>,
+    pub dedupe_window_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ClientSection {
+    pub remote_addr: Option<SocketAddr>,
+    pub server_name: Option<String>,
+    pub ca_path: Option<PathBuf>,
+    pub cert_path: Option<PathBuf>,
+    pub key_path: Option<PathBuf>,
+    pub group: Option<String>,
+    pub processed_path: Option<PathBuf>,
+}
+
+impl FileConfig {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let raw = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config {}: {e}", path.display()))?;
+        toml::from_str(&raw).map_err(|e| format!("Invalid config {}: {e}", path.display()))
+    }
+
+    /// Load the file named by `H3X_CONFIG`, or an empty config if unset.
+    pub fn from_env() -> Result<Self, String> {
+        match std::env::var(CONFIG_ENV) {
+            Ok(path) => Self::load(Path::new(&path)),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+}
+
+/// Read and parse an env var, `None` if unset, error if set but malformed.
+pub fn env_parse<T: std::str::FromStr>(key: &str) -> Result<Option<T>, String>
+where
+    T::Err: std::fmt::Display,
+{
+    match std::env::var(key) {
+        Ok(raw) => raw
+            .parse()
+            .map(Some)
+            .map_err(|e| format!("Invalid {key}={raw}: {e}")),
+        Err(_) => Ok(None),
+    }
+}
@@ -1,6 +1,8 @@
+pub mod admin;
 pub mod protocol;
 pub mod utils;
 pub mod client;
+pub mod config;
 pub mod server;
 pub mod state;
 pub mod tls;
\ No newline at end of file
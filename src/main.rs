@@ -1,20 +1,25 @@
-mod tls;
-mod server;
-mod client;
-mod protocol;
-mod utils;
-mod state;
-
+use anyhow::anyhow;
 use tokio::signal;
 use dotenv::dotenv;
 
-use client::builder::ClientBuilder;
+use h3x::admin;
+use h3x::client::builder::ClientBuilder;
+use h3x::client::{replay, run_client};
+use h3x::protocol::h3x as pb;
+use h3x::server;
+use h3x::server::builder::ServerBuilder;
+use h3x::state::registry::{Access, ClientMetadata, RegistryStore};
 use tokio_util::sync::CancellationToken;
 
-use crate::client::run_client;
-
 #[tokio::main]
 async fn main() {
+    if let Err(e) = run().await {
+        eprintln!("❌ {e:#}");
+        std::process::exit(1);
+    }
+}
+
+async fn run() -> anyhow::Result<()> {
     dotenv().ok();
     let token = std::env::var("H3X_CLIENT_TOKEN").unwrap_or("default_token".into());
     let ns = std::env::var("H3X_CLIENT_NAMESPACE").unwrap_or_else(|_| "default_namespace".into());
@@ -23,9 +28,23 @@ async fn main() {
     let args: Vec<String> = std::env::args().collect();
     match args.get(1).map(|s| s.as_str()) {
         Some("server") => {
-            let server_task = tokio::spawn(async {
-                server::run_server(id, token, ns).await;
-            });
+            let params = ServerBuilder::from_env()
+                .and_then(|b| b.build())
+                .map_err(|e| anyhow!("Failed to build server params: {e}"))?;
+
+            // First run: seed the registry with the env-configured client.
+            let mut meta = ClientMetadata::new(id, &token);
+            let seeded = meta
+                .grant(ns.clone(), Access::Read)
+                .and_then(|_| meta.grant(ns, Access::Write))
+                .and_then(|_| RegistryStore::new(&params.registry_path).seed_if_empty(meta));
+            match seeded {
+                Ok(true) => println!("🌱 Seeded registry at {}", params.registry_path.display()),
+                Ok(false) => {}
+                Err(e) => eprintln!("❌ Failed to seed registry: {e:#}"),
+            }
+
+            let server_task = tokio::spawn(server::run_server(params));
 
             let shutdown_task = tokio::spawn(async {
                 signal::ctrl_c().await.expect("Failed to listen for Ctrl+C");
@@ -33,7 +52,7 @@ async fn main() {
             });
 
             tokio::select! {
-                _ = server_task => {},
+                res = server_task => res??,
                 _ = shutdown_task => {},
             }
 
@@ -41,12 +60,13 @@ async fn main() {
         }
 
         Some("client") => {
-            let params = ClientBuilder::new()
+            let params = ClientBuilder::from_env()
+                .map_err(|e| anyhow!("Failed to load client config: {e}"))?
                 .namespace(ns)
                 .token(token)
                 .client_id(id)
                 .build()
-                .expect("Failed to build client params");
+                .map_err(|e| anyhow!("Failed to build client params: {e}"))?;
 
             let cancel_token = CancellationToken::new();
             let client_cancel = cancel_token.clone();
@@ -67,6 +87,69 @@ async fn main() {
             }
         }
 
-        _ => eprintln!("Usage: cargo run -- [server|client]"),
+        Some("admin") => {
+            let params = ServerBuilder::from_env()
+                .and_then(|b| b.build())
+                .map_err(|e| anyhow!("Failed to build server params: {e}"))?;
+
+            if let Err(e) = admin::run(&params, &args[2..]) {
+                eprintln!("❌ {e:#}\n{}", admin::USAGE);
+                std::process::exit(1);
+            }
+        }
+
+        Some("replay") => {
+            let params = ClientBuilder::from_env()
+                .map_err(|e| anyhow!("Failed to load client config: {e}"))?
+                .namespace(ns.clone())
+                .token(token)
+                .client_id(id)
+                .build()
+                .map_err(|e| anyhow!("Failed to build client params: {e}"))?;
+
+            let request = match replay_request(&args[2..], ns) {
+                Ok(request) => request,
+                Err(e) => {
+                    eprintln!("❌ {e}\n{REPLAY_USAGE}");
+                    std::process::exit(1);
+                }
+            };
+
+            if let Err(e) = replay(&params, request).await {
+                eprintln!("❌ Replay failed: {e:#}");
+                std::process::exit(1);
+            }
+        }
+
+        _ => eprintln!("Usage: cargo run -- [server|client|admin|replay]"),
     }
+    Ok(())
+}
+
+const REPLAY_USAGE: &str = "\
+Usage: h3x replay [namespace] [--from-offset <n>] [--since <unix secs>] [--until <unix secs>] [--limit <n>]
+  namespace defaults to H3X_CLIENT_NAMESPACE; --until is exclusive";
+
+/// Build a Replay request from `h3x replay` arguments.
+fn replay_request(args: &[String], default_ns: String) -> Result<pb::Replay, String> {
+    let mut request = pb::Replay { namespace: default_ns, ..Default::default() };
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--from-offset" => request.from_offset = parse(arg, value()?)?,
+            "--since" => request.from_timestamp = parse(arg, value()?)?,
+            "--until" => request.to_timestamp = parse(arg, value()?)?,
+            "--limit" => request.limit = parse(arg, value()?)?,
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {flag}")),
+            namespace => request.namespace = namespace.to_string(),
+        }
+    }
+
+    Ok(request)
+}
+
+fn parse<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("Invalid value for {flag}: {value:?}"))
 }
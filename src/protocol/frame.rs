@@ -1,4 +1,5 @@
-use crate::protocol::h3x::Frame;
+use crate::protocol::h3x::{AckEvent, Error, ErrorCode, Frame};
+use std::fmt;
 use prost::Message;
 use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
@@ -44,10 +45,7 @@ impl Frame {
             .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "length too large"))?;
 
         if len > max_len {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                format!("frame too large: {} > {}", len, max_len),
-            ));
+            return Err(io::Error::new(io::ErrorKind::InvalidData, FrameTooLarge { len, max_len }));
         }
 
         // 2) Read exact number of message bytes.
@@ -68,6 +66,29 @@ impl Frame {
     }
 }
 
+/// Error inside the `io::Error` from `read_from_with_limit` when a frame's
+/// length prefix exceeds the limit. The frame's bytes are left unread.
+#[derive(Debug)]
+pub struct FrameTooLarge {
+    pub len: usize,
+    pub max_len: usize,
+}
+
+impl FrameTooLarge {
+    /// The `FrameTooLarge` wrapped in `err`, if that is what it holds.
+    pub fn find(err: &io::Error) -> Option<&Self> {
+        err.get_ref().and_then(|inner| inner.downcast_ref())
+    }
+}
+
+impl fmt::Display for FrameTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "frame too large: {} > {}", self.len, self.max_len)
+    }
+}
+
+impl std::error::Error for FrameTooLarge {}
+
 /// Read a protobuf varint (u64) from `reader`.
 /// Returns (value, saw_eof_early):
 /// - `saw_eof_early = true` means we hit EOF before reading any varint byte (clean EOF).
@@ -109,3 +130,45 @@ async fn read_varint_u64<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<(u6
         "varint too long",
     ))
 }
+
+impl AckEvent {
+    /// Ack for several events of one namespace (batched form).
+    pub fn batch(namespace: String, event_ids: Vec<String>) -> Self {
+        Self {
+            namespace,
+            event_id: String::new(),
+            event_ids,
+            duplicates: Default::default(),
+        }
+    }
+
+    /// Every id this ack covers: the single-id field plus the batched list.
+    pub fn ids(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.event_id.as_str())
+            .filter(|id| !id.is_empty())
+            .chain(self.event_ids.iter().map(String::as_str))
+    }
+}
+
+impl Error {
+    /// Error payload; `request_id` is the event id, or the stream id of the
+    /// rejected frame when the failure is not about one event.
+    pub fn new(code: ErrorCode, message: impl Into<String>, request_id: impl ToString) -> Self {
+        Self {
+            message: message.into(),
+            supported_versions: Vec::new(),
+            code: code as i32,
+            request_id: request_id.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code(), self.message)?;
+        if !self.request_id.is_empty() {
+            write!(f, " (request {})", self.request_id)?;
+        }
+        Ok(())
+    }
+}
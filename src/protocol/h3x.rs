@@ -1,4 +1,39 @@
 // This file is @generated by prost-build.
+/// First frame on a connection, before Auth: the protocol versions the client
+/// speaks and the capabilities it would like to use. Its envelope version is
+/// not checked, so any client can negotiate.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hello {
+    #[prost(uint32, repeated, tag = "1")]
+    pub versions: ::prost::alloc::vec::Vec<u32>,
+    #[prost(enumeration = "Capability", repeated, tag = "2")]
+    pub capabilities: ::prost::alloc::vec::Vec<i32>,
+}
+/// Server reply to Hello: the highest common version, which every later frame
+/// carries, and the requested capabilities the server supports.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct HelloAck {
+    #[prost(uint32, tag = "1")]
+    pub version: u32,
+    #[prost(enumeration = "Capability", repeated, tag = "2")]
+    pub capabilities: ::prost::alloc::vec::Vec<i32>,
+}
+/// Details of a failed request. Sent as the payload of AuthError and Nack
+/// replies, and as an Error frame for failures without such a reply.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Error {
+    /// human-readable
+    #[prost(string, tag = "1")]
+    pub message: ::prost::alloc::string::String,
+    /// set with ERROR_CODE_BAD_VERSION
+    #[prost(uint32, repeated, tag = "2")]
+    pub supported_versions: ::prost::alloc::vec::Vec<u32>,
+    #[prost(enumeration = "ErrorCode", tag = "3")]
+    pub code: i32,
+    /// event id for failures about one event, else the frame's stream id
+    #[prost(string, tag = "4")]
+    pub request_id: ::prost::alloc::string::String,
+}
 /// Sent by client to authenticate itself.
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Auth {
@@ -28,35 +63,137 @@ pub struct Event {
     /// Unix seconds
     #[prost(int64, tag = "6")]
     pub timestamp: i64,
+    /// "ordering_key": same-key events go out one at a time, in order;
     #[prost(map = "string, string", tag = "7")]
     pub metadata: ::std::collections::HashMap<
         ::prost::alloc::string::String,
         ::prost::alloc::string::String,
     >,
+    /// "idempotency_key": dedupe key used instead of the id
+    ///
+    /// position in its namespace; set by the server on delivery, ignored on publish
+    #[prost(uint64, tag = "8")]
+    pub offset: u64,
+    /// Unix ms; held back until then (0 = deliver now)
+    #[prost(int64, tag = "9")]
+    pub deliver_at: i64,
+    /// alternative to deliver_at, counted from arrival at the server
+    #[prost(uint64, tag = "10")]
+    pub delay_ms: u64,
+    /// Unix ms; undelivered after this (0 = namespace default TTL, if any)
+    #[prost(int64, tag = "11")]
+    pub expires_at: i64,
 }
 /// Request from client to fetch queued events.
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct FetchEvents {
     #[prost(string, repeated, tag = "1")]
     pub namespaces: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
-    /// optional limit
+    /// max events in the whole batch; 0 = server default
     #[prost(uint32, tag = "2")]
     pub limit: u32,
+    /// namespace -> `next_cursors` value from the previous batch
+    #[prost(map = "string, string", tag = "3")]
+    pub cursors: ::std::collections::HashMap<
+        ::prost::alloc::string::String,
+        ::prost::alloc::string::String,
+    >,
+    /// consumer group; empty = "default"
+    #[prost(string, tag = "4")]
+    pub group: ::prost::alloc::string::String,
 }
-/// Acknowledges receipt of a specific event.
+/// Acknowledges receipt of one or more events in a namespace.
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct AckEvent {
+    #[prost(string, tag = "1")]
+    pub namespace: ::prost::alloc::string::String,
+    /// UUID as string; single-id form, still accepted
+    #[prost(string, tag = "2")]
+    pub event_id: ::prost::alloc::string::String,
+    /// batched form; combined with event_id if both are set
+    #[prost(string, repeated, tag = "3")]
+    pub event_ids: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// Publish acks only: published id -> id of the event already stored under the
+    /// same dedupe key (idempotency_key metadata, else the id) within the window.
+    #[prost(map = "string, string", tag = "4")]
+    pub duplicates: ::std::collections::HashMap<
+        ::prost::alloc::string::String,
+        ::prost::alloc::string::String,
+    >,
+}
+/// Server reply to a consumer's AckEvent: which ids were deleted and which
+/// were not stored (already acked, expired or never existed).
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AckResult {
+    #[prost(string, tag = "1")]
+    pub namespace: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag = "2")]
+    pub acked: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(string, repeated, tag = "3")]
+    pub not_found: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// Negative acknowledgement of a delivered event.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Nack {
     #[prost(string, tag = "1")]
     pub namespace: ::prost::alloc::string::String,
     /// UUID as string
     #[prost(string, tag = "2")]
     pub event_id: ::prost::alloc::string::String,
+    /// why the consumer rejected it
+    #[prost(string, tag = "3")]
+    pub reason: ::prost::alloc::string::String,
+    /// hide the event this long before redelivery (0 = right away)
+    #[prost(uint32, tag = "4")]
+    pub requeue_delay_ms: u32,
+}
+/// Ask the server to push newly enqueued events on this stream.
+/// The server answers with an Ack once the subscription is live, then sends
+/// Event/EventsBatch frames; the client acks or nacks them on the same stream.
+/// Each consumer group gets every event once; its members share the work.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Subscribe {
+    /// empty = every namespace of the session
+    #[prost(string, repeated, tag = "1")]
+    pub namespaces: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// consumer group; empty = "default"
+    #[prost(string, tag = "2")]
+    pub group: ::prost::alloc::string::String,
+}
+/// Stream a namespace's retained history, acked or not, without leasing it.
+/// The server answers with EventsBatch frames until one has has_more = false.
+/// Zero means "unset" for every field.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Replay {
+    #[prost(string, tag = "1")]
+    pub namespace: ::prost::alloc::string::String,
+    /// first offset to include (Event.offset)
+    #[prost(uint64, tag = "2")]
+    pub from_offset: u64,
+    /// Unix seconds, inclusive
+    #[prost(int64, tag = "3")]
+    pub from_timestamp: i64,
+    /// Unix seconds, exclusive
+    #[prost(int64, tag = "4")]
+    pub to_timestamp: i64,
+    /// max events in total
+    #[prost(uint32, tag = "5")]
+    pub limit: u32,
 }
 /// Batch of events sent from server to client.
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct EventsBatch {
     #[prost(message, repeated, tag = "1")]
     pub events: ::prost::alloc::vec::Vec<Event>,
+    /// opaque; send back in FetchEvents.cursors to continue. Only moves past settled events
+    #[prost(map = "string, string", tag = "2")]
+    pub next_cursors: ::std::collections::HashMap<
+        ::prost::alloc::string::String,
+        ::prost::alloc::string::String,
+    >,
+    /// the batch filled up before every stored event was scanned
+    #[prost(bool, tag = "3")]
+    pub has_more: bool,
 }
 /// Ping Pong
 #[derive(Clone, Copy, PartialEq, ::prost::Message)]
@@ -80,6 +217,20 @@ pub struct Pong {
     #[prost(uint64, tag = "3")]
     pub seq: u64,
 }
+/// An event moved to its namespace's dead-letter tree after too many failed
+/// deliveries. Stored by the server only; never sent on the wire.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DeadLetter {
+    #[prost(message, optional, tag = "1")]
+    pub event: ::core::option::Option<Event>,
+    #[prost(uint32, tag = "2")]
+    pub attempts: u32,
+    /// most recent last
+    #[prost(string, repeated, tag = "3")]
+    pub reasons: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(uint64, tag = "4")]
+    pub dead_at_ms: u64,
+}
 /// The main transport frame.
 /// All data is sent as a length-delimited Frame over the stream.
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -94,7 +245,10 @@ pub struct Frame {
     #[prost(enumeration = "FrameType", tag = "3")]
     pub r#type: i32,
     /// Exactly one payload should be set per frame.
-    #[prost(oneof = "frame::Payload", tags = "10, 11, 12, 13, 14, 15, 16")]
+    #[prost(
+        oneof = "frame::Payload",
+        tags = "10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23"
+    )]
     pub payload: ::core::option::Option<frame::Payload>,
 }
 /// Nested message and enum types in `Frame`.
@@ -118,6 +272,20 @@ pub mod frame {
         /// NEW
         #[prost(message, tag = "16")]
         Pong(super::Pong),
+        #[prost(message, tag = "17")]
+        Nack(super::Nack),
+        #[prost(message, tag = "18")]
+        Subscribe(super::Subscribe),
+        #[prost(message, tag = "19")]
+        AckResult(super::AckResult),
+        #[prost(message, tag = "20")]
+        Replay(super::Replay),
+        #[prost(message, tag = "21")]
+        Hello(super::Hello),
+        #[prost(message, tag = "22")]
+        HelloAck(super::HelloAck),
+        #[prost(message, tag = "23")]
+        Error(super::Error),
     }
 }
 /// Enum representing all supported frame types.
@@ -138,6 +306,12 @@ pub enum FrameType {
     Nack = 9,
     AuthAck = 10,
     AuthError = 11,
+    Subscribe = 12,
+    AckResult = 13,
+    Replay = 14,
+    Hello = 15,
+    HelloAck = 16,
+    Error = 17,
 }
 impl FrameType {
     /// String value of the enum field names used in the ProtoBuf definition.
@@ -158,6 +332,12 @@ impl FrameType {
             Self::Nack => "FRAME_TYPE_NACK",
             Self::AuthAck => "FRAME_TYPE_AUTH_ACK",
             Self::AuthError => "FRAME_TYPE_AUTH_ERROR",
+            Self::Subscribe => "FRAME_TYPE_SUBSCRIBE",
+            Self::AckResult => "FRAME_TYPE_ACK_RESULT",
+            Self::Replay => "FRAME_TYPE_REPLAY",
+            Self::Hello => "FRAME_TYPE_HELLO",
+            Self::HelloAck => "FRAME_TYPE_HELLO_ACK",
+            Self::Error => "FRAME_TYPE_ERROR",
         }
     }
     /// Creates an enum from field names used in the ProtoBuf definition.
@@ -175,6 +355,100 @@ impl FrameType {
             "FRAME_TYPE_NACK" => Some(Self::Nack),
             "FRAME_TYPE_AUTH_ACK" => Some(Self::AuthAck),
             "FRAME_TYPE_AUTH_ERROR" => Some(Self::AuthError),
+            "FRAME_TYPE_SUBSCRIBE" => Some(Self::Subscribe),
+            "FRAME_TYPE_ACK_RESULT" => Some(Self::AckResult),
+            "FRAME_TYPE_REPLAY" => Some(Self::Replay),
+            "FRAME_TYPE_HELLO" => Some(Self::Hello),
+            "FRAME_TYPE_HELLO_ACK" => Some(Self::HelloAck),
+            "FRAME_TYPE_ERROR" => Some(Self::Error),
+            _ => None,
+        }
+    }
+}
+/// Optional features a connection can agree on in Hello/HelloAck.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum Capability {
+    Unspecified = 0,
+    /// EventsBatch frames for publishing
+    Batching = 1,
+    /// compressed payloads (not offered by this server yet)
+    Compression = 2,
+    /// Subscribe and pushed events
+    Subscriptions = 3,
+}
+impl Capability {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            Self::Unspecified => "CAPABILITY_UNSPECIFIED",
+            Self::Batching => "CAPABILITY_BATCHING",
+            Self::Compression => "CAPABILITY_COMPRESSION",
+            Self::Subscriptions => "CAPABILITY_SUBSCRIPTIONS",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "CAPABILITY_UNSPECIFIED" => Some(Self::Unspecified),
+            "CAPABILITY_BATCHING" => Some(Self::Batching),
+            "CAPABILITY_COMPRESSION" => Some(Self::Compression),
+            "CAPABILITY_SUBSCRIPTIONS" => Some(Self::Subscriptions),
+            _ => None,
+        }
+    }
+}
+/// Why the server refused or failed a request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum ErrorCode {
+    Unspecified = 0,
+    /// no successful Auth on the connection, or bad credentials
+    Unauthenticated = 1,
+    /// the client holds no grant for the namespace
+    ForbiddenNamespace = 2,
+    /// the stream is closed after this one
+    FrameTooLarge = 3,
+    /// the queue could not read or write; retrying may work
+    StorageFailure = 4,
+    /// reserved, not sent yet
+    RateLimited = 5,
+    /// see supported_versions
+    BadVersion = 6,
+    /// missing payload, unknown frame type, bad group, ...
+    InvalidRequest = 7,
+}
+impl ErrorCode {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            Self::Unspecified => "ERROR_CODE_UNSPECIFIED",
+            Self::Unauthenticated => "ERROR_CODE_UNAUTHENTICATED",
+            Self::ForbiddenNamespace => "ERROR_CODE_FORBIDDEN_NAMESPACE",
+            Self::FrameTooLarge => "ERROR_CODE_FRAME_TOO_LARGE",
+            Self::StorageFailure => "ERROR_CODE_STORAGE_FAILURE",
+            Self::RateLimited => "ERROR_CODE_RATE_LIMITED",
+            Self::BadVersion => "ERROR_CODE_BAD_VERSION",
+            Self::InvalidRequest => "ERROR_CODE_INVALID_REQUEST",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "ERROR_CODE_UNSPECIFIED" => Some(Self::Unspecified),
+            "ERROR_CODE_UNAUTHENTICATED" => Some(Self::Unauthenticated),
+            "ERROR_CODE_FORBIDDEN_NAMESPACE" => Some(Self::ForbiddenNamespace),
+            "ERROR_CODE_FRAME_TOO_LARGE" => Some(Self::FrameTooLarge),
+            "ERROR_CODE_STORAGE_FAILURE" => Some(Self::StorageFailure),
+            "ERROR_CODE_RATE_LIMITED" => Some(Self::RateLimited),
+            "ERROR_CODE_BAD_VERSION" => Some(Self::BadVersion),
+            "ERROR_CODE_INVALID_REQUEST" => Some(Self::InvalidRequest),
             _ => None,
         }
     }
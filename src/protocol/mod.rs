@@ -1,2 +1,3 @@
 pub mod h3x;     // generated file at src/protocol/h3x.rs
 pub mod frame;   // your helpers
+pub mod version; // Hello/HelloAck negotiation
@@ -0,0 +1,99 @@
+// version.rs
+// Protocol version and capability negotiation (Hello/HelloAck). Every frame
+// carries the version in its envelope; a connection that sends Hello agrees on
+// one version and a set of capabilities before Auth. Connections that skip
+// Hello are treated as version 1 clients with every capability.
+
+use crate::protocol::h3x::{Capability, Hello, HelloAck};
+
+/// Version this build sends in every frame envelope.
+pub const PROTO_VERSION: u32 = 1;
+
+/// Versions this build can parse, oldest first.
+pub const SUPPORTED_VERSIONS: &[u32] = &[PROTO_VERSION];
+
+/// Capabilities this build implements.
+pub const CAPABILITIES: &[Capability] = &[Capability::Batching, Capability::Subscriptions];
+
+/// What a connection agreed on in Hello/HelloAck.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Negotiated {
+    pub version: u32,
+    pub capabilities: Vec<Capability>,
+}
+
+impl Negotiated {
+    pub fn allows(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+}
+
+impl From<&HelloAck> for Negotiated {
+    fn from(ack: &HelloAck) -> Self {
+        Self {
+            version: ack.version,
+            capabilities: ack.capabilities().collect(),
+        }
+    }
+}
+
+/// Hello offering every supported version and capability.
+pub fn hello() -> Hello {
+    let mut hello = Hello {
+        versions: SUPPORTED_VERSIONS.to_vec(),
+        capabilities: Vec::new(),
+    };
+    for &capability in CAPABILITIES {
+        hello.push_capabilities(capability);
+    }
+    hello
+}
+
+/// Pick the highest version both sides speak and the requested capabilities
+/// this build implements. Errors when there is no common version.
+pub fn negotiate(hello: &Hello) -> Result<Negotiated, String> {
+    let version = SUPPORTED_VERSIONS
+        .iter()
+        .rev()
+        .find(|v| hello.versions.contains(v))
+        .copied()
+        .ok_or_else(|| format!("no common protocol version in {:?}", hello.versions))?;
+
+    let mut capabilities: Vec<Capability> = hello.capabilities().filter(|c| CAPABILITIES.contains(c)).collect();
+    capabilities.sort();
+    capabilities.dedup();
+
+    Ok(Negotiated { version, capabilities })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offer(versions: &[u32], capabilities: &[i32]) -> Hello {
+        Hello { versions: versions.to_vec(), capabilities: capabilities.to_vec() }
+    }
+
+    #[test]
+    fn negotiates_the_highest_common_version() {
+        assert_eq!(negotiate(&offer(&[PROTO_VERSION, 99], &[])).unwrap().version, PROTO_VERSION);
+        assert_eq!(negotiate(&hello()).unwrap().version, PROTO_VERSION);
+        assert!(negotiate(&offer(&[0, 99], &[])).is_err());
+        assert!(negotiate(&offer(&[], &[])).is_err());
+    }
+
+    #[test]
+    fn keeps_only_implemented_capabilities() {
+        let requested = [
+            Capability::Subscriptions as i32,
+            Capability::Compression as i32,
+            Capability::Batching as i32,
+            Capability::Subscriptions as i32,
+            42,
+        ];
+        let negotiated = negotiate(&offer(&[PROTO_VERSION], &requested)).unwrap();
+        assert_eq!(negotiated.capabilities, [Capability::Batching, Capability::Subscriptions]);
+        assert!(!negotiated.allows(Capability::Compression));
+        assert!(negotiate(&offer(&[PROTO_VERSION], &[])).unwrap().capabilities.is_empty());
+    }
+}
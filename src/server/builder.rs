@@ -0,0 +1,279 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::config::{env_parse, FileConfig};
+use crate::server::params::ServerParams;
+use crate::state::policy::{NamespacePolicy, Policies};
+use crate::state::queue::DEFAULT_VISIBILITY_TIMEOUT;
+
+pub struct ServerBuilder {
+    bind_addr: SocketAddr,
+    data_dir: PathBuf,
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    tls_dev: bool,
+    client_ca_path: Option<PathBuf>,
+    registry_path: Option<PathBuf>,
+    visibility_timeout: Duration,
+    policies: Policies,
+}
+
+impl Default for ServerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ServerBuilder {
+    pub fn new() -> Self {
+        Self {
+            bind_addr: "127.0.0.1:5000".parse().unwrap(),
+            data_dir: "data".into(),
+            cert_path: "cert.der".into(),
+            key_path: "key.der".into(),
+            tls_dev: false,
+            client_ca_path: None,
+            registry_path: None,
+            visibility_timeout: DEFAULT_VISIBILITY_TIMEOUT,
+            policies: Policies::default(),
+        }
+    }
+
+    /// Defaults, then the `H3X_CONFIG` file, then `H3X_*` env vars.
+    pub fn from_env() -> Result<Self, String> {
+        Self::new().file(&FileConfig::from_env()?).env()
+    }
+
+    pub fn bind_addr(mut self, addr: SocketAddr) -> Self {
+        self.bind_addr = addr;
+        self
+    }
+
+    pub fn data_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.data_dir = dir.into();
+        self
+    }
+
+    pub fn cert_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.cert_path = path.into();
+        self
+    }
+
+    pub fn key_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.key_path = path.into();
+        self
+    }
+
+    /// Generate a self-signed certificate at the cert/key paths if missing.
+    pub fn tls_dev(mut self, enabled: bool) -> Self {
+        self.tls_dev = enabled;
+        self
+    }
+
+    /// Require client certificates signed by this CA bundle (mTLS).
+    pub fn client_ca_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.client_ca_path = Some(path.into());
+        self
+    }
+
+    /// Client registry file. Defaults to `registry.json` inside the data directory.
+    pub fn registry_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.registry_path = Some(path.into());
+        self
+    }
+
+    /// How long fetched or pushed events stay invisible waiting for an ack.
+    pub fn visibility_timeout(mut self, timeout: Duration) -> Self {
+        self.visibility_timeout = timeout;
+        self
+    }
+
+    /// Dead-letter events after this many failed deliveries, unless a
+    /// namespace policy says otherwise.
+    pub fn max_deliveries(mut self, max: u32) -> Self {
+        self.policies.default.max_deliveries = Some(max);
+        self
+    }
+
+    /// Keep acked events and drop them only once they are older than `max_age`,
+    /// unless a namespace policy says otherwise.
+    pub fn retention_max_age(mut self, max_age: Duration) -> Self {
+        self.policies.default.retention_max_age_secs = Some(max_age.as_secs());
+        self
+    }
+
+    /// Keep acked events and drop the oldest once a namespace stores more than `max` bytes.
+    pub fn retention_max_bytes(mut self, max: u64) -> Self {
+        self.policies.default.retention_max_bytes = Some(max);
+        self
+    }
+
+    /// Keep acked events and drop the oldest beyond `max` per namespace.
+    pub fn retention_max_count(mut self, max: u64) -> Self {
+        self.policies.default.retention_max_count = Some(max);
+        self
+    }
+
+    /// Expire events `ttl` after they are enqueued unless they carry their own
+    /// `expires_at`, unless a namespace policy says otherwise.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.policies.default.ttl_secs = Some(ttl.as_secs());
+        self
+    }
+
+    /// Dead-letter expired events for every consumer group instead of deleting them.
+    pub fn dead_letter_expired(mut self, enabled: bool) -> Self {
+        self.policies.default.dead_letter_expired = Some(enabled);
+        self
+    }
+
+    /// Deduplicate publishes by event id (or `idempotency_key` metadata) within
+    /// `window`, unless a namespace policy says otherwise.
+    pub fn dedupe_window(mut self, window: Duration) -> Self {
+        self.policies.default.dedupe_window_secs = Some(window.as_secs());
+        self
+    }
+
+    /// Override the server defaults for one namespace.
+    pub fn namespace_policy<T: Into<String>>(mut self, namespace: T, policy: NamespacePolicy) -> Self {
+        self.policies.namespaces.insert(namespace.into(), policy);
+        self
+    }
+
+    /// Apply the `[server]` and `[namespaces.*]` sections of a config file.
+    pub fn file(mut self, config: &FileConfig) -> Self {
+        let s = &config.server;
+        if let Some(addr) = s.bind_addr {
+            self.bind_addr = addr;
+        }
+        if let Some(dir) = &s.data_dir {
+            self.data_dir = dir.clone();
+        }
+        if let Some(path) = &s.cert_path {
+            self.cert_path = path.clone();
+        }
+        if let Some(path) = &s.key_path {
+            self.key_path = path.clone();
+        }
+        if let Some(dev) = s.tls_dev {
+            self.tls_dev = dev;
+        }
+        if let Some(path) = &s.client_ca_path {
+            self.client_ca_path = Some(path.clone());
+        }
+        if let Some(path) = &s.registry_path {
+            self.registry_path = Some(path.clone());
+        }
+        if let Some(secs) = s.visibility_timeout_secs {
+            self.visibility_timeout = Duration::from_secs(secs);
+        }
+        if let Some(max) = s.max_deliveries {
+            self.policies.default.max_deliveries = Some(max);
+        }
+        if let Some(secs) = s.retention_max_age_secs {
+            self.policies.default.retention_max_age_secs = Some(secs);
+        }
+        if let Some(max) = s.retention_max_bytes {
+            self.policies.default.retention_max_bytes = Some(max);
+        }
+        if let Some(max) = s.retention_max_count {
+            self.policies.default.retention_max_count = Some(max);
+        }
+        if let Some(secs) = s.ttl_secs {
+            self.policies.default.ttl_secs = Some(secs);
+        }
+        if let Some(enabled) = s.dead_letter_expired {
+            self.policies.default.dead_letter_expired = Some(enabled);
+        }
+        if let Some(secs) = s.dedupe_window_secs {
+            self.policies.default.dedupe_window_secs = Some(secs);
+        }
+        for (ns, policy) in &config.namespaces {
+            self.policies.namespaces.insert(ns.clone(), policy.clone());
+        }
+        self
+    }
+
+    /// Apply `H3X_BIND_ADDR`, `H3X_DATA_DIR`, `H3X_CERT_PATH`, `H3X_KEY_PATH`,
+    /// `H3X_TLS_DEV`, `H3X_CLIENT_CA_PATH`, `H3X_REGISTRY_PATH`,
+    /// `H3X_VISIBILITY_TIMEOUT_SECS`, `H3X_MAX_DELIVERIES`, `H3X_RETENTION_MAX_AGE_SECS`,
+    /// `H3X_RETENTION_MAX_BYTES`, `H3X_RETENTION_MAX_COUNT`, `H3X_TTL_SECS`,
+    /// `H3X_DEAD_LETTER_EXPIRED` and `H3X_DEDUPE_WINDOW_SECS`.
+    pub fn env(mut self) -> Result<Self, String> {
+        if let Some(addr) = env_parse("H3X_BIND_ADDR")? {
+            self.bind_addr = addr;
+        }
+        if let Some(dir) = env_parse::<PathBuf>("H3X_DATA_DIR")? {
+            self.data_dir = dir;
+        }
+        if let Some(path) = env_parse::<PathBuf>("H3X_CERT_PATH")? {
+            self.cert_path = path;
+        }
+        if let Some(path) = env_parse::<PathBuf>("H3X_KEY_PATH")? {
+            self.key_path = path;
+        }
+        if let Some(dev) = env_parse("H3X_TLS_DEV")? {
+            self.tls_dev = dev;
+        }
+        if let Some(path) = env_parse::<PathBuf>("H3X_CLIENT_CA_PATH")? {
+            self.client_ca_path = Some(path);
+        }
+        if let Some(path) = env_parse::<PathBuf>("H3X_REGISTRY_PATH")? {
+            self.registry_path = Some(path);
+        }
+        if let Some(secs) = env_parse("H3X_VISIBILITY_TIMEOUT_SECS")? {
+            self.visibility_timeout = Duration::from_secs(secs);
+        }
+        if let Some(max) = env_parse("H3X_MAX_DELIVERIES")? {
+            self.policies.default.max_deliveries = Some(max);
+        }
+        if let Some(secs) = env_parse("H3X_RETENTION_MAX_AGE_SECS")? {
+            self.policies.default.retention_max_age_secs = Some(secs);
+        }
+        if let Some(max) = env_parse("H3X_RETENTION_MAX_BYTES")? {
+            self.policies.default.retention_max_bytes = Some(max);
+        }
+        if let Some(max) = env_parse("H3X_RETENTION_MAX_COUNT")? {
+            self.policies.default.retention_max_count = Some(max);
+        }
+        if let Some(secs) = env_parse("H3X_TTL_SECS")? {
+            self.policies.default.ttl_secs = Some(secs);
+        }
+        if let Some(enabled) = env_parse("H3X_DEAD_LETTER_EXPIRED")? {
+            self.policies.default.dead_letter_expired = Some(enabled);
+        }
+        if let Some(secs) = env_parse("H3X_DEDUPE_WINDOW_SECS")? {
+            self.policies.default.dedupe_window_secs = Some(secs);
+        }
+        Ok(self)
+    }
+
+    pub fn build(self) -> Result<ServerParams, String> {
+        if self.data_dir.as_os_str().is_empty() {
+            return Err("Data directory must not be empty".into());
+        }
+
+        if self.visibility_timeout.is_zero() {
+            return Err("Visibility timeout must be greater than zero".into());
+        }
+
+        self.policies.validate()?;
+
+        let registry_path = self
+            .registry_path
+            .unwrap_or_else(|| self.data_dir.join("registry.json"));
+
+        Ok(ServerParams {
+            bind_addr: self.bind_addr,
+            data_dir: self.data_dir,
+            cert_path: self.cert_path,
+            key_path: self.key_path,
+            tls_dev: self.tls_dev,
+            client_ca_path: self.client_ca_path,
+            registry_path,
+            visibility_timeout: self.visibility_timeout,
+            policies: self.policies,
+        })
+    }
+}
@@ -1,43 +1,232 @@
 // src/server/handlers.rs
 use quinn::{RecvStream, SendStream};
 
+use std::collections::{BTreeMap, HashMap};
 use std::convert::TryFrom;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
 
-use prost::Message;
-
-use crate::state::queue::EventQueue;
-use crate::state::registry::NamespaceRegistry;
+use super::session::{ConnectionSession, Session};
+use super::MAX_FRAME_LEN;
+use crate::protocol::frame::FrameTooLarge;
+use crate::state::queue::{
+    decode_cursor, encode_cursor, valid_group, valid_namespace, Enqueued, EventQueue, FailureOutcome, HistoryRange, DEFAULT_GROUP,
+};
+use crate::protocol::version::{negotiate, PROTO_VERSION, SUPPORTED_VERSIONS};
+use crate::state::registry::{is_allowed, Access, NamespaceRegistry};
 use crate::utils::validate_auth;
 
 use crate::protocol::h3x::{
     frame, // oneof namespace
     AckEvent,
+    AckResult,
+    Capability,
+    Error,
+    ErrorCode,
     Event,
     EventsBatch,
     FetchEvents,
     Frame as H3XFrame,
     FrameType,
+    HelloAck,
+    Nack,
     Ping,
     Pong,
+    Replay,
+    Subscribe,
 };
 
-const PROTO_VERSION: u32 = 1;
+/// Pushed events buffered per subscription stream before forwarders wait.
+const SUBSCRIBER_BUFFER: usize = 256;
+
+/// Batch size for a FetchEvents with `limit: 0`, and the cap for larger limits.
+const DEFAULT_FETCH_LIMIT: usize = 100;
+const MAX_FETCH_LIMIT: usize = 1000;
+
+/// Batch size for a FetchEvents `limit`.
+fn fetch_limit(limit: u32) -> usize {
+    match limit as usize {
+        0 => DEFAULT_FETCH_LIMIT,
+        n => n.min(MAX_FETCH_LIMIT),
+    }
+}
+
+/// What a subscription's namespace forwarders hand its writing loop.
+enum Pushed {
+    /// A newly enqueued event, to lease and push.
+    Event(Event),
+    /// The namespace's broadcast dropped events; push its backlog instead.
+    Lagged(String),
+}
 
 // --- Small helpers ----------------------------------------------------------
 
+/// Read the next frame from a client stream, refusing any over `MAX_FRAME_LEN`.
+pub async fn read_frame(recv: &mut RecvStream) -> Result<Option<H3XFrame>, std::io::Error> {
+    H3XFrame::read_from_with_limit(recv, MAX_FRAME_LEN).await
+}
+
+/// Report a failed `read_frame`. An oversized frame is still unread, so the
+/// stream cannot continue either way; the client is told when it was too large.
+pub async fn read_failed(send: &mut SendStream, e: &std::io::Error) {
+    let Some(too_large) = FrameTooLarge::find(e) else {
+        eprintln!("❌ Stream read error: {:?}", e);
+        return;
+    };
+    let stream_id: u32 = send.id().index().try_into().unwrap_or(0);
+    let error = Error::new(ErrorCode::FrameTooLarge, too_large.to_string(), stream_id);
+    send_error(send, stream_id, error).await;
+    if let Err(e) = send.finish().await {
+        eprintln!("❌ Failed to close stream after oversized frame: {e}");
+    }
+}
+
 async fn write_frame(send: &mut SendStream, frame: &H3XFrame) -> Result<(), std::io::Error> {
     frame
         .write_to(send)
         .await
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+        .map_err(std::io::Error::other)
+}
+
+/// Return the connection's session, or reply with `reject` (AuthError/Nack)
+/// when the connection has not authenticated yet.
+async fn require_session(
+    session: &ConnectionSession,
+    send: &mut SendStream,
+    stream_id: u32,
+    reject: FrameType,
+) -> Option<Session> {
+    if let Some(s) = session.session.read().await.clone() {
+        return Some(s);
+    }
+
+    let error = Error::new(ErrorCode::Unauthenticated, "connection has not authenticated", stream_id);
+    send_reject(send, stream_id, reject, error).await;
+    None
+}
+
+/// Publishing variant of `require_session`: rejects every event in `events`
+/// with its own Nack, so the publisher can fail each pending publish.
+async fn require_publish_session(
+    session: &ConnectionSession,
+    send: &mut SendStream,
+    stream_id: u32,
+    events: &[Event],
+) -> Option<Session> {
+    if let Some(s) = session.session.read().await.clone() {
+        return Some(s);
+    }
+
+    for ev in events {
+        let error = Error::new(ErrorCode::Unauthenticated, "connection has not authenticated", &ev.id);
+        send_reject(send, stream_id, FrameType::Nack, error).await;
+    }
+    None
+}
+
+/// Why the session's client may not use `namespace` with `access`: an invalid
+/// name, or no such grant in the registry right now. `None` when it may.
+async fn access_denied(
+    registry: &NamespaceRegistry,
+    session: &Session,
+    namespace: &str,
+    access: Access,
+) -> Option<(ErrorCode, String)> {
+    if !valid_namespace(namespace) {
+        return Some((ErrorCode::InvalidRequest, format!("invalid namespace {:?}", namespace)));
+    }
+    if is_allowed(registry, &session.client_id, namespace, access).await {
+        return None;
+    }
+    let message = format!("client_id={} lacks {:?} access to namespace {}", session.client_id, access, namespace);
+    Some((ErrorCode::ForbiddenNamespace, message))
+}
+
+/// Reply with `reject` (AuthError/Nack) unless `namespace` is a valid name and
+/// the session's client currently holds `access` on it in the registry.
+async fn authorize(
+    registry: &NamespaceRegistry,
+    session: &Session,
+    namespace: &str,
+    access: Access,
+    send: &mut SendStream,
+    stream_id: u32,
+    reject: FrameType,
+) -> bool {
+    let Some((code, message)) = access_denied(registry, session, namespace, access).await else {
+        return true;
+    };
+    send_reject(send, stream_id, reject, Error::new(code, message, stream_id)).await;
+    false
+}
+
+/// Publishing variant of `authorize`: the Nack names the rejected event.
+async fn authorize_publish(
+    registry: &NamespaceRegistry,
+    session: &Session,
+    ev: &Event,
+    send: &mut SendStream,
+    stream_id: u32,
+) -> bool {
+    let Some((code, message)) = access_denied(registry, session, &ev.namespace, Access::Write).await else {
+        return true;
+    };
+    send_reject(send, stream_id, FrameType::Nack, Error::new(code, message, &ev.id)).await;
+    false
+}
+
+/// Consumer group named in a FetchEvents/Subscribe, `default` when empty.
+/// Replies with Nack and returns `None` for an invalid name.
+async fn consumer_group(requested: String, send: &mut SendStream, stream_id: u32) -> Option<String> {
+    if requested.is_empty() {
+        return Some(DEFAULT_GROUP.to_string());
+    }
+    if valid_group(&requested) {
+        return Some(requested);
+    }
+
+    let message = format!("invalid consumer group {:?}", requested);
+    send_reject(send, stream_id, FrameType::Nack, Error::new(ErrorCode::InvalidRequest, message, stream_id)).await;
+    None
+}
+
+/// Report a failed request with an Error frame.
+pub async fn send_error(send: &mut SendStream, stream_id: u32, error: Error) {
+    eprintln!("❌ Request failed on stream {stream_id}: {error}");
+    let reply = H3XFrame {
+        version: PROTO_VERSION,
+        stream_id,
+        r#type: FrameType::Error as i32,
+        payload: Some(frame::Payload::Error(error)),
+    };
+    if let Err(e) = write_frame(send, &reply).await {
+        eprintln!("❌ Failed to send Error: {e}");
+    }
+}
+
+/// Reply with `reject` (AuthError/Nack), carrying `error` as its payload.
+async fn send_reject(send: &mut SendStream, stream_id: u32, reject: FrameType, error: Error) {
+    eprintln!("❌ Rejecting frame on stream {stream_id} with {:?}: {error}", reject);
+    let reply = H3XFrame {
+        version: PROTO_VERSION,
+        stream_id,
+        r#type: reject as i32,
+        payload: Some(frame::Payload::Error(error)),
+    };
+    if let Err(e) = write_frame(send, &reply).await {
+        eprintln!("❌ Failed to send {:?}: {e}", reject);
+    }
 }
 
-fn decode_stored_frame(bytes: &[u8]) -> Option<H3XFrame> {
-    // If you stored frames with `encode_length_delimited`, use the corresponding decode.
-    // If you stored raw `encode`, `decode` is fine as well. Try both if you’re migrating.
-    H3XFrame::decode(bytes)
-        .ok()
-        .or_else(|| H3XFrame::decode_length_delimited(bytes).ok())
+/// Error frame for a frame of type `ft` that arrived without its payload.
+async fn missing_payload(send: &mut SendStream, stream_id: u32, ft: FrameType) {
+    let message = format!("{:?} frame missing payload", ft);
+    send_error(send, stream_id, Error::new(ErrorCode::InvalidRequest, message, stream_id)).await;
+}
+
+/// Error frame for a queue failure while serving `request_id`.
+async fn storage_failure(send: &mut SendStream, stream_id: u32, request_id: impl ToString, message: String) {
+    send_error(send, stream_id, Error::new(ErrorCode::StorageFailure, message, request_id)).await;
 }
 
 // --- Handlers ---------------------------------------------------------------
@@ -46,13 +235,14 @@ pub async fn handle_auth(
     frame: H3XFrame,
     registry: NamespaceRegistry,
     send: &mut SendStream,
-) {
+    peer_names: Option<&[String]>,
+) -> Option<Session> {
     let Some(frame::Payload::Auth(auth)) = frame.payload else {
-        eprintln!("❌ Auth frame missing payload");
-        return;
+        missing_payload(send, frame.stream_id, FrameType::Auth).await;
+        return None;
     };
 
-    let is_valid = validate_auth(&auth, &registry).await;
+    let is_valid = validate_auth(&auth, &registry, peer_names).await;
 
     if is_valid {
         println!("🔐 Authenticated client_id={} namespaces={:?}", auth.client_id, auth.namespaces);
@@ -68,124 +258,364 @@ pub async fn handle_auth(
         if let Err(e) = write_frame(send, &ack).await {
             eprintln!("❌ Failed to send AuthAck: {e}");
         }
-    } else {
-        eprintln!("❌ Invalid auth for client_id={}", auth.client_id);
 
-        // Send AuthError (or Nack) and close stream
-        let nack = H3XFrame {
-            version: PROTO_VERSION,
-            stream_id: frame.stream_id,
-            r#type: FrameType::AuthError as i32,
-            payload: None,
+        // No explicit list means every namespace the client holds a grant on.
+        let namespaces = if auth.namespaces.is_empty() {
+            registry
+                .read()
+                .await
+                .get(&auth.client_id)
+                .map(|meta| meta.read_namespaces.union(&meta.write_namespaces).cloned().collect())
+                .unwrap_or_default()
+        } else {
+            auth.namespaces
         };
-        if let Err(e) = write_frame(send, &nack).await {
-            eprintln!("❌ Failed to send AuthError: {e}");
-        }
+
+        Some(Session {
+            client_id: auth.client_id,
+            namespaces,
+        })
+    } else {
+        let message = format!("invalid credentials for client_id={}", auth.client_id);
+        let error = Error::new(ErrorCode::Unauthenticated, message, frame.stream_id);
+        send_reject(send, frame.stream_id, FrameType::AuthError, error).await;
         if let Err(e) = send.finish().await {
             eprintln!("❌ Failed to close stream after AuthError: {e}");
         }
+
+        None
     }
 }
 
-pub async fn handle_ack_event(frame: H3XFrame, sled_db: &sled::Db) {
+pub async fn handle_ack_event(
+    frame: H3XFrame,
+    send: &mut SendStream,
+    session: &ConnectionSession,
+    registry: NamespaceRegistry,
+    queue: &EventQueue,
+) {
+    let Some(session) = require_session(session, send, frame.stream_id, FrameType::Nack).await else {
+        return;
+    };
+
     let Some(frame::Payload::AckEvent(ack)) = frame.payload else {
-        eprintln!("❌ AckEvent frame missing payload");
+        missing_payload(send, frame.stream_id, FrameType::AckEvent).await;
         return;
     };
 
-    let tree = match sled_db.open_tree(&ack.namespace) {
-        Ok(tree) => tree,
+    // Acks outside a FetchEvents/Subscribe stream settle for the default group.
+    apply_ack(ack, send, frame.stream_id, &session, &registry, queue, DEFAULT_GROUP).await;
+}
+
+/// Settle every id in the ack (single-id or batched form) for `group` in one
+/// transaction and reply with an AckResult listing which ids were pending.
+async fn apply_ack(
+    ack: AckEvent,
+    send: &mut SendStream,
+    stream_id: u32,
+    session: &Session,
+    registry: &NamespaceRegistry,
+    queue: &EventQueue,
+    group: &str,
+) {
+    let ids: Vec<&str> = ack.ids().collect();
+    println!("✅ Received Ack for {} event(s) in namespace {} (group {group})", ids.len(), ack.namespace);
+
+    if !authorize(registry, session, &ack.namespace, Access::Read, send, stream_id, FrameType::Nack).await {
+        return;
+    }
+
+    let (acked, not_found) = match queue.ack_many(&ack.namespace, group, &ids) {
+        Ok(result) => result,
         Err(e) => {
-            eprintln!("❌ Failed to open sled tree for ack: {e}");
+            storage_failure(send, stream_id, stream_id, format!("failed to record acked events: {e}")).await;
             return;
         }
     };
 
-    let key = ack.event_id.as_bytes();
-    match tree.remove(key) {
-        Ok(_) => println!("✅ Acked and deleted event {}", ack.event_id),
-        Err(e) => eprintln!("❌ Failed to delete acked event: {e}"),
+    println!("🧹 Settled {} acknowledged event(s) for group {group}", acked.len());
+    if !not_found.is_empty() {
+        println!("⚠️ Events not pending for group {group}: {:?}", not_found);
+    }
+
+    let reply = H3XFrame {
+        version: PROTO_VERSION,
+        stream_id,
+        r#type: FrameType::AckResult as i32,
+        payload: Some(frame::Payload::AckResult(AckResult {
+            namespace: ack.namespace,
+            acked,
+            not_found,
+        })),
+    };
+    if let Err(e) = write_frame(send, &reply).await {
+        eprintln!("❌ Failed to send AckResult: {e}");
+    }
+}
+
+pub async fn handle_nack(
+    frame: H3XFrame,
+    send: &mut SendStream,
+    session: &ConnectionSession,
+    registry: NamespaceRegistry,
+    queue: &EventQueue,
+) {
+    let Some(session) = require_session(session, send, frame.stream_id, FrameType::Nack).await else {
+        return;
+    };
+
+    let Some(frame::Payload::Nack(nack)) = frame.payload else {
+        missing_payload(send, frame.stream_id, FrameType::Nack).await;
+        return;
+    };
+
+    apply_nack(nack, send, frame.stream_id, &session, &registry, queue, DEFAULT_GROUP).await;
+}
+
+/// Count `group`'s failed attempt and requeue the event after the requested delay.
+async fn apply_nack(
+    nack: Nack,
+    send: &mut SendStream,
+    stream_id: u32,
+    session: &Session,
+    registry: &NamespaceRegistry,
+    queue: &EventQueue,
+    group: &str,
+) {
+    println!("↩️ Nack for event {} in namespace {}: {}", nack.event_id, nack.namespace, nack.reason);
+
+    if !authorize(registry, session, &nack.namespace, Access::Read, send, stream_id, FrameType::Nack).await {
+        return;
+    }
+
+    let delay = Duration::from_millis(nack.requeue_delay_ms.into());
+    match queue.nack(&nack.namespace, group, &nack.event_id, &nack.reason, delay) {
+        Ok(Some(FailureOutcome::Requeued(attempts))) => {
+            println!("🔁 Requeued event {} in {:?} (attempt {attempts})", nack.event_id, delay)
+        }
+        Ok(Some(FailureOutcome::DeadLettered(attempts))) => {
+            println!("🪦 Event {} dead-lettered after {attempts} failed deliveries", nack.event_id)
+        }
+        Ok(None) => {
+            let message = format!("event {} in {} is not leased by group {group}", nack.event_id, nack.namespace);
+            send_error(send, stream_id, Error::new(ErrorCode::InvalidRequest, message, &nack.event_id)).await;
+        }
+        Err(e) => {
+            let message = format!("failed to requeue nacked event: {e}");
+            storage_failure(send, stream_id, &nack.event_id, message).await;
+        }
     }
 }
 
 pub async fn handle_event(
     frame: H3XFrame,
+    send: &mut SendStream,
+    session: &ConnectionSession,
     registry: NamespaceRegistry,
     queue: EventQueue,
 ) {
     let Some(frame::Payload::Event(event)) = frame.payload else {
-        eprintln!("❌ Event frame missing payload");
+        missing_payload(send, frame.stream_id, FrameType::Event).await;
+        return;
+    };
+
+    let Some(session) = require_publish_session(session, send, frame.stream_id, std::slice::from_ref(&event)).await
+    else {
         return;
     };
 
     let ns = event.namespace.clone();
 
-    if registry.read().await.contains_key(&ns) {
-        println!("📨 [{}] EVENT: {}", ns, event.message);
+    if authorize_publish(&registry, &session, &event, send, frame.stream_id).await {
+        println!("📨 [{}] EVENT from {}: {}", ns, session.client_id, event.message);
+
+        let event_id = event.id.clone();
 
         // Re‑wrap into a prost Frame so your queue can persist the full envelope
-        let mut stored = H3XFrame {
+        let stored = H3XFrame {
             version: PROTO_VERSION,
             stream_id: frame.stream_id,
             r#type: FrameType::Event as i32,
             payload: Some(frame::Payload::Event(event)),
         };
 
-        if let Err(e) = queue.enqueue(&mut stored) {
-            eprintln!("❌ Failed to persist event to queue: {e}");
+        let mut duplicates = HashMap::new();
+        match queue.enqueue_once(&stored) {
+            Ok(Enqueued::Stored(_)) => {}
+            Ok(Enqueued::Duplicate(original)) => {
+                println!("♊ Duplicate publish of {} in {}, original {}", event_id, ns, original);
+                duplicates.insert(event_id.clone(), original);
+            }
+            Err(e) => {
+                let message = format!("failed to persist event: {e}");
+                storage_failure(send, frame.stream_id, &event_id, message).await;
+                return;
+            }
+        }
+
+        // Acknowledge so the publisher can resolve its pending publish
+        let ack = H3XFrame {
+            version: PROTO_VERSION,
+            stream_id: frame.stream_id,
+            r#type: FrameType::AckEvent as i32,
+            payload: Some(frame::Payload::AckEvent(AckEvent {
+                namespace: ns,
+                event_id,
+                event_ids: Vec::new(),
+                duplicates,
+            })),
+        };
+
+        if let Err(e) = write_frame(send, &ack).await {
+            eprintln!("❌ Failed to send AckEvent: {e}");
         }
-    } else {
-        eprintln!("❌ Unknown namespace: {}", ns);
     }
 }
 
 pub async fn handle_events_batch(
     frame: H3XFrame,
     send: &mut SendStream,
+    session: &ConnectionSession,
     registry: NamespaceRegistry,
     queue: EventQueue,
 ) {
-    let Some(frame::Payload::EventsBatch(EventsBatch { events })) = frame.payload else {
-        eprintln!("❌ EventsBatch frame missing payload");
+    let Some(frame::Payload::EventsBatch(EventsBatch { events, .. })) = frame.payload else {
+        missing_payload(send, frame.stream_id, FrameType::EventsBatch).await;
         return;
     };
 
+    let Some(session) = require_publish_session(session, send, frame.stream_id, &events).await else {
+        return;
+    };
+
+    // Stored ids per namespace, acknowledged with one frame each at the end.
+    let mut stored: BTreeMap<String, AckEvent> = BTreeMap::new();
+
     for ev in events {
         let ns = ev.namespace.clone();
 
-        if registry.read().await.contains_key(&ns) {
+        if authorize_publish(&registry, &session, &ev, send, frame.stream_id).await {
             println!("📦 BATCH EVENT [{}]: {}", ns, ev.r#type);
 
+            let event_id = ev.id.clone();
+
             // Persist each as its own Event frame
-            let mut store_frame = H3XFrame {
+            let store_frame = H3XFrame {
                 version: PROTO_VERSION,
                 stream_id: frame.stream_id,
                 r#type: FrameType::Event as i32,
-                payload: Some(frame::Payload::Event(ev.clone())),
+                payload: Some(frame::Payload::Event(ev)),
             };
 
-            if let Err(e) = queue.enqueue(&mut store_frame) {
-                eprintln!("❌ Failed to enqueue event for {}: {}", ns, e);
-                continue;
+            let original = match queue.enqueue_once(&store_frame) {
+                Ok(Enqueued::Stored(_)) => None,
+                Ok(Enqueued::Duplicate(original)) => Some(original),
+                Err(e) => {
+                    let message = format!("failed to persist event in {ns}: {e}");
+                    storage_failure(send, frame.stream_id, &event_id, message).await;
+                    continue;
+                }
+            };
+
+            let ack = stored.entry(ns.clone()).or_insert_with(|| AckEvent::batch(ns, Vec::new()));
+            if let Some(original) = original {
+                ack.duplicates.insert(event_id.clone(), original);
             }
+            ack.event_ids.push(event_id);
+        }
+    }
 
-            // Acknowledge this event back to the client
-            let ack = H3XFrame {
-                version: PROTO_VERSION,
-                stream_id: frame.stream_id,
-                r#type: FrameType::AckEvent as i32,
-                payload: Some(frame::Payload::AckEvent(AckEvent {
-                    namespace: ns.clone(),
-                    event_id: ev.id.clone(),
-                })),
+    for (ns, ack) in stored {
+        if !ack.duplicates.is_empty() {
+            println!("♊ {} duplicate publish(es) in {}", ack.duplicates.len(), ns);
+        }
+        let ack = H3XFrame {
+            version: PROTO_VERSION,
+            stream_id: frame.stream_id,
+            r#type: FrameType::AckEvent as i32,
+            payload: Some(frame::Payload::AckEvent(ack)),
+        };
+
+        if let Err(e) = write_frame(send, &ack).await {
+            eprintln!("❌ Failed to send AckEvent for {}: {}", ns, e);
+        }
+    }
+}
+
+/// Agree on a protocol version and capabilities before Auth. Replies with
+/// HelloAck, or with an Error listing the supported versions when there is no
+/// common one.
+pub async fn handle_hello(frame: H3XFrame, send: &mut SendStream, session: &ConnectionSession) {
+    let Some(frame::Payload::Hello(hello)) = frame.payload else {
+        missing_payload(send, frame.stream_id, FrameType::Hello).await;
+        return;
+    };
+
+    if session.protocol.read().await.is_some() || session.session.read().await.is_some() {
+        let message = "Hello must be the first frame of a connection";
+        send_error(send, frame.stream_id, Error::new(ErrorCode::InvalidRequest, message, frame.stream_id)).await;
+        return;
+    }
+
+    let negotiated = match negotiate(&hello) {
+        Ok(negotiated) => negotiated,
+        Err(message) => {
+            let error = Error {
+                supported_versions: SUPPORTED_VERSIONS.to_vec(),
+                ..Error::new(ErrorCode::BadVersion, message, frame.stream_id)
             };
+            send_error(send, frame.stream_id, error).await;
+            return;
+        }
+    };
+    println!("👋 Negotiated protocol v{} with {:?}", negotiated.version, negotiated.capabilities);
 
-            if let Err(e) = write_frame(send, &ack).await {
-                eprintln!("❌ Failed to send AckEvent {}: {}", ev.id, e);
-            }
-        } else {
-            eprintln!("❌ Unknown namespace in batch: {}", ns);
+    let mut ack = HelloAck { version: negotiated.version, capabilities: Vec::new() };
+    for &capability in &negotiated.capabilities {
+        ack.push_capabilities(capability);
+    }
+    *session.protocol.write().await = Some(negotiated);
+
+    let reply = H3XFrame {
+        version: PROTO_VERSION,
+        stream_id: frame.stream_id,
+        r#type: FrameType::HelloAck as i32,
+        payload: Some(frame::Payload::HelloAck(ack)),
+    };
+    if let Err(e) = write_frame(send, &reply).await {
+        eprintln!("❌ Failed to send HelloAck: {e}");
+    }
+}
+
+/// Check a frame's envelope version and the capability its type needs
+/// against what the connection negotiated (any supported version and every
+/// capability without Hello). Returns the Error to reply with otherwise.
+async fn check_protocol(frame: &H3XFrame, ft: FrameType, session: &ConnectionSession) -> Option<Error> {
+    let negotiated = session.protocol.read().await.clone();
+
+    let version_ok = match &negotiated {
+        Some(n) => frame.version == n.version,
+        None => SUPPORTED_VERSIONS.contains(&frame.version),
+    };
+    if !version_ok {
+        let message = format!("unsupported protocol version {}", frame.version);
+        return Some(Error {
+            supported_versions: negotiated.map_or_else(|| SUPPORTED_VERSIONS.to_vec(), |n| vec![n.version]),
+            ..Error::new(ErrorCode::BadVersion, message, frame.stream_id)
+        });
+    }
+
+    let needs = match ft {
+        FrameType::EventsBatch => Capability::Batching,
+        FrameType::Subscribe => Capability::Subscriptions,
+        _ => return None,
+    };
+    match negotiated {
+        Some(n) if !n.allows(needs) => {
+            let message = format!("{:?} frames need the {:?} capability, which was not negotiated", ft, needs);
+            Some(Error::new(ErrorCode::InvalidRequest, message, frame.stream_id))
         }
+        _ => None,
     }
 }
 
@@ -220,56 +650,77 @@ pub async fn handle_fetch_events(
     frame: H3XFrame,
     send: &mut SendStream,
     recv: &mut RecvStream,
-    sled_db: &sled::Db,
+    connection: &ConnectionSession,
+    registry: NamespaceRegistry,
+    queue: &EventQueue,
 ) {
-    let Some(frame::Payload::FetchEvents(FetchEvents { namespaces, limit })) = frame.payload else {
-        eprintln!("❌ FetchEvents frame missing payload");
+    let Some(session) = require_session(connection, send, frame.stream_id, FrameType::AuthError).await else {
+        return;
+    };
+
+    let Some(frame::Payload::FetchEvents(FetchEvents { mut namespaces, limit, cursors, group })) = frame.payload else {
+        missing_payload(send, frame.stream_id, FrameType::FetchEvents).await;
         return;
     };
+    let Some(group) = consumer_group(group, send, frame.stream_id).await else {
+        return;
+    };
+
+    // An empty request means "everything this session authenticated for".
+    if namespaces.is_empty() {
+        namespaces = session.namespaces.clone();
+    }
+    println!("🔍 FetchEvents from client_id={} namespaces={:?} group={}", session.client_id, namespaces, group);
 
-    let prefixes: Vec<String> = namespaces.iter().map(|ns| format!("{}:", ns)).collect();
+    // All-or-nothing: one unreadable namespace rejects the whole fetch.
+    for ns in &namespaces {
+        if !authorize(&registry, &session, ns, Access::Read, send, frame.stream_id, FrameType::AuthError).await {
+            return;
+        }
+    }
+    join_groups(queue, &namespaces, &group);
+
+    let limit = fetch_limit(limit);
     let mut events: Vec<Event> = Vec::new();
+    let mut next_cursors = HashMap::new();
+    let mut has_more = false;
 
-    // 1) Scan persisted frames and collect Events up to limit
-    for prefix in prefixes {
-        println!("🔍 Fetching events for namespace prefix: {}", prefix);    
-        // Use sled's scan_prefix to get all keys starting with the namespace prefix
-        for result in sled_db.scan_prefix(prefix.as_bytes()).take(limit as usize) {
-            match result {
-                Ok((_key, value)) => {
-                    if let Some(stored_frame) = decode_stored_frame(&value) {
-                        // Ensure it's an Event and extract it
-                        let Ok(ft) = FrameType::try_from(stored_frame.r#type) else {
-                            eprintln!("⚠️ Unknown stored frame type: {}", stored_frame.r#type);
-                            continue;
-                        };
-                        if ft != FrameType::Event {
-                            eprintln!("⚠️ Skipped non-Event stored frame: {:?}", ft);
-                            continue;
-                        }
-                        match stored_frame.payload {
-                            Some(frame::Payload::Event(ev)) => {
-                                events.push(ev);
-                            }
-                            _ => eprintln!("⚠️ Stored Event frame missing payload"),
-                        }
-                    } else {
-                        eprintln!("❌ Failed to decode stored frame (not prost?)");
-                    }
+    // 1) Lease visible Events per namespace, in enqueue order, after each
+    //    namespace's cursor, until the batch holds `limit` events
+    for ns in &namespaces {
+        let after = match cursors.get(ns) {
+            Some(c) => match decode_cursor(c) {
+                Some(seq) => Some(seq),
+                None => {
+                    eprintln!("⚠️ Invalid cursor {:?} for {}, starting from the beginning", c, ns);
+                    None
                 }
-                Err(e) => {
-                    eprintln!("❌ Sled DB scan error: {:?}", e);
+            },
+            None => None,
+        };
+
+        println!("🔍 Fetching events for namespace: {}", ns);
+        match queue.lease_events(ns, &group, after, limit - events.len()) {
+            Ok(mut page) => {
+                events.append(&mut page.events);
+                has_more |= page.has_more;
+                if let Some(seq) = page.cursor {
+                    next_cursors.insert(ns.clone(), encode_cursor(seq));
                 }
             }
+            Err(e) => {
+                let message = format!("failed to fetch events for {ns}: {e}");
+                storage_failure(send, frame.stream_id, frame.stream_id, message).await;
+            }
         }
     }
-    
+
     // 2) Send EventsBatch to client
     let response = H3XFrame {
         version: PROTO_VERSION,
         stream_id: frame.stream_id,
         r#type: FrameType::EventsBatch as i32,
-        payload: Some(frame::Payload::EventsBatch(EventsBatch { events })),
+        payload: Some(frame::Payload::EventsBatch(EventsBatch { events, next_cursors, has_more })),
     };
 
     println!(
@@ -287,66 +738,436 @@ pub async fn handle_fetch_events(
         println!("✅ EventsBatch response sent.");
     }
 
-    // 3) Wait for acks and delete from sled
+    // 3) Wait for acks and settle them for the group
     println!("📨 Waiting for AckEvent frames...");
 
     loop {
-        match H3XFrame::read_from(recv).await {
-            Ok(Some(ack_frame)) => {
-                let Ok(ft) = FrameType::try_from(ack_frame.r#type) else {
-                    eprintln!("⚠️ Unknown frame type value: {}", ack_frame.r#type);
-                    continue;
-                };
+        match read_frame(recv).await {
+            Ok(Some(reply)) => handle_consumer_reply(reply, send, connection, &session, &registry, queue, &group).await,
+            Ok(None) => {
+                println!("📴 Client closed stream after sending Acks.");
+                break;
+            }
+            Err(e) => {
+                read_failed(send, &e).await;
+                break;
+            }
+        }
+    }
+}
+
+/// Stream a namespace's retained history as EventsBatch frames of at most
+/// `DEFAULT_FETCH_LIMIT` events. Read-only: nothing is leased or acked, so
+/// consumer groups are unaffected. The last batch has `has_more: false`.
+pub async fn handle_replay(
+    frame: H3XFrame,
+    send: &mut SendStream,
+    session: &ConnectionSession,
+    registry: NamespaceRegistry,
+    queue: &EventQueue,
+) {
+    let Some(session) = require_session(session, send, frame.stream_id, FrameType::AuthError).await else {
+        return;
+    };
+
+    let Some(frame::Payload::Replay(Replay { namespace: ns, from_offset, from_timestamp, to_timestamp, limit })) =
+        frame.payload
+    else {
+        missing_payload(send, frame.stream_id, FrameType::Replay).await;
+        return;
+    };
+    if !authorize(&registry, &session, &ns, Access::Read, send, frame.stream_id, FrameType::AuthError).await {
+        return;
+    }
+
+    let range = HistoryRange {
+        from_offset: (from_offset != 0).then_some(from_offset),
+        from_timestamp: (from_timestamp != 0).then_some(from_timestamp),
+        to_timestamp: (to_timestamp != 0).then_some(to_timestamp),
+    };
+    let mut remaining = match limit as usize {
+        0 => usize::MAX,
+        n => n,
+    };
+    println!("⏪ Replay from client_id={} namespace={} range={:?}", session.client_id, ns, range);
 
-                if ft == FrameType::AckEvent {
-                    let Some(frame::Payload::AckEvent(AckEvent { namespace, event_id })) = ack_frame.payload else {
-                        eprintln!("⚠️ AckEvent missing payload");
-                        continue;
-                    };
+    let mut after = None;
+    let mut sent = 0usize;
+    loop {
+        let page = match queue.history(&ns, &range, after, remaining.min(DEFAULT_FETCH_LIMIT)) {
+            Ok(page) => page,
+            Err(e) => {
+                let message = format!("failed to read history for {ns}: {e}");
+                storage_failure(send, frame.stream_id, frame.stream_id, message).await;
+                Default::default()
+            }
+        };
 
-                    println!("✅ Received Ack for event {} in namespace {}", event_id, namespace);
+        remaining -= page.events.len();
+        sent += page.events.len();
+        let has_more = page.has_more && remaining > 0;
+        after = page.cursor;
 
-                    let key = format!("{}:{}", namespace, event_id);
-                    match sled_db.remove(key.as_bytes()) {
-                        Ok(Some(_)) => println!("🧹 Removed acknowledged event from sled: {}", key),
-                        Ok(None) => println!("⚠️ Event not found in sled: {}", key),
-                        Err(e) => eprintln!("❌ Failed to remove event from sled: {:?}", e),
+        let next_cursors = page.cursor.map(|seq| (ns.clone(), encode_cursor(seq))).into_iter().collect();
+        let response = H3XFrame {
+            version: PROTO_VERSION,
+            stream_id: frame.stream_id,
+            r#type: FrameType::EventsBatch as i32,
+            payload: Some(frame::Payload::EventsBatch(EventsBatch { events: page.events, next_cursors, has_more })),
+        };
+        if let Err(e) = write_frame(send, &response).await {
+            eprintln!("❌ Failed to send replayed events: {e}");
+            return;
+        }
+        if !has_more {
+            break;
+        }
+    }
+
+    println!("✅ Replayed {sent} event(s) from {ns}");
+}
+
+/// Register `group` with every namespace so events are kept until it settles them.
+fn join_groups(queue: &EventQueue, namespaces: &[String], group: &str) {
+    for ns in namespaces {
+        match queue.join_group(ns, group) {
+            Ok(true) => println!("👥 Consumer group {group} joined {ns}"),
+            Ok(false) => {}
+            Err(e) => eprintln!("❌ Failed to register group {group} on {ns}: {e}"),
+        }
+    }
+}
+
+/// AckEvent or Nack sent back by a `group` consumer for an event it was delivered.
+/// Checked against the connection's protocol like any other frame.
+async fn handle_consumer_reply(
+    reply: H3XFrame,
+    send: &mut SendStream,
+    connection: &ConnectionSession,
+    session: &Session,
+    registry: &NamespaceRegistry,
+    queue: &EventQueue,
+    group: &str,
+) {
+    if let Ok(ft) = FrameType::try_from(reply.r#type)
+        && let Some(error) = check_protocol(&reply, ft, connection).await
+    {
+        send_error(send, reply.stream_id, error).await;
+        return;
+    }
+
+    match (FrameType::try_from(reply.r#type), reply.payload) {
+        (Ok(FrameType::AckEvent), Some(frame::Payload::AckEvent(ack))) => {
+            apply_ack(ack, send, reply.stream_id, session, registry, queue, group).await
+        }
+        (Ok(FrameType::Nack), Some(frame::Payload::Nack(nack))) => {
+            apply_nack(nack, send, reply.stream_id, session, registry, queue, group).await
+        }
+        (Ok(ft @ (FrameType::AckEvent | FrameType::Nack)), _) => missing_payload(send, reply.stream_id, ft).await,
+        (kind, _) => {
+            let message = format!("expected AckEvent or Nack from consumer, got {:?}", kind);
+            send_error(send, reply.stream_id, Error::new(ErrorCode::InvalidRequest, message, reply.stream_id)).await;
+        }
+    }
+}
+
+/// Push events to the client as they are enqueued, until it closes the stream.
+/// Acks and Nacks for pushed events come back on the same stream.
+pub async fn handle_subscribe(
+    frame: H3XFrame,
+    send: &mut SendStream,
+    recv: &mut RecvStream,
+    connection: &ConnectionSession,
+    registry: NamespaceRegistry,
+    queue: &EventQueue,
+) {
+    let Some(session) = require_session(connection, send, frame.stream_id, FrameType::AuthError).await else {
+        return;
+    };
+
+    let Some(frame::Payload::Subscribe(Subscribe { mut namespaces, group })) = frame.payload else {
+        missing_payload(send, frame.stream_id, FrameType::Subscribe).await;
+        return;
+    };
+    let Some(group) = consumer_group(group, send, frame.stream_id).await else {
+        return;
+    };
+
+    if namespaces.is_empty() {
+        namespaces = session.namespaces.clone();
+    }
+
+    for ns in &namespaces {
+        if !authorize(&registry, &session, ns, Access::Read, send, frame.stream_id, FrameType::AuthError).await {
+            return;
+        }
+    }
+    join_groups(queue, &namespaces, &group);
+
+    // Fan every namespace's broadcast into one channel for this stream.
+    let (tx, mut rx) = mpsc::channel::<Pushed>(SUBSCRIBER_BUFFER);
+    let forwarders: Vec<_> = namespaces
+        .iter()
+        .map(|ns| {
+            let mut events = queue.subscribe(ns);
+            let tx = tx.clone();
+            let ns = ns.clone();
+            tokio::spawn(async move {
+                loop {
+                    match events.recv().await {
+                        Ok(ev) => {
+                            if tx.send(Pushed::Event(ev)).await.is_err() {
+                                break;
+                            }
+                        }
+                        Err(broadcast::error::RecvError::Lagged(n)) => {
+                            eprintln!("⚠️ Subscriber lagged on {ns}; catching up on {n} missed event(s)");
+                            if tx.send(Pushed::Lagged(ns.clone())).await.is_err() {
+                                break;
+                            }
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    }
+                }
+            })
+        })
+        .collect();
+    drop(tx);
+
+    println!("📡 client_id={} subscribed to {:?} in group {}", session.client_id, namespaces, group);
+
+    // Tell the client the subscription is live so it can fetch the backlog without gaps.
+    let ready = H3XFrame {
+        version: PROTO_VERSION,
+        stream_id: frame.stream_id,
+        r#type: FrameType::Ack as i32,
+        payload: None,
+    };
+    if let Err(e) = write_frame(send, &ready).await {
+        eprintln!("❌ Failed to confirm subscription: {e}");
+    } else {
+        // `read_frame` is not cancel-safe, so replies are read in their own
+        // loop; a read error is passed on for the writer to report.
+        let (reply_tx, mut replies) = mpsc::channel::<Result<H3XFrame, std::io::Error>>(SUBSCRIBER_BUFFER);
+        let reading = async move {
+            loop {
+                match read_frame(recv).await {
+                    Ok(Some(reply)) => {
+                        if reply_tx.send(Ok(reply)).await.is_err() {
+                            break;
+                        }
+                    }
+                    Ok(None) => {
+                        println!("📴 Subscriber closed stream.");
+                        break;
+                    }
+                    Err(e) => {
+                        let _ = reply_tx.send(Err(e)).await;
+                        break;
                     }
-                } else {
-                    eprintln!("⚠️ Unexpected frame after EventsBatch: {:?}", ft);
                 }
             }
-            Ok(None) => {
-                println!("📴 Client closed stream after sending Acks.");
-                break;
+        };
+
+        let writing = async {
+            loop {
+                tokio::select! {
+                    pushed = rx.recv() => {
+                        let Some(first) = pushed else { break };
+                        let mut events = Vec::new();
+                        let mut lagged = Vec::new();
+                        for pushed in std::iter::once(first).chain(std::iter::from_fn(|| rx.try_recv().ok())) {
+                            match pushed {
+                                Pushed::Event(ev) => events.push(ev),
+                                Pushed::Lagged(ns) if !lagged.contains(&ns) => lagged.push(ns),
+                                Pushed::Lagged(_) => {}
+                            }
+                        }
+
+                        // Grants may have been revoked since the subscription started,
+                        // and another member of the group may already hold the lease.
+                        let mut allowed = Vec::with_capacity(events.len());
+                        for ev in events {
+                            if !is_allowed(&registry, &session.client_id, &ev.namespace, Access::Read).await {
+                                continue;
+                            }
+                            match queue.lease(&ev.namespace, &group, &ev.id) {
+                                Ok(true) => allowed.push(ev),
+                                Ok(false) => {}
+                                Err(e) => {
+                                    let message = format!("failed to lease pushed event: {e}");
+                                    storage_failure(send, frame.stream_id, &ev.id, message).await;
+                                }
+                            }
+                        }
+
+                        if let Err(e) = push_events(send, frame.stream_id, allowed).await {
+                            eprintln!("❌ Failed to push events: {e}");
+                            break;
+                        }
+
+                        // Missed broadcasts are still stored; lease and push what the group has not settled.
+                        for ns in &lagged {
+                            if is_allowed(&registry, &session.client_id, ns, Access::Read).await
+                                && let Err(e) = push_backlog(send, frame.stream_id, queue, ns, &group).await
+                            {
+                                eprintln!("❌ Failed to push missed events: {e}");
+                                return;
+                            }
+                        }
+                    }
+                    reply = replies.recv() => match reply {
+                        Some(Ok(reply)) => handle_consumer_reply(reply, send, connection, &session, &registry, queue, &group).await,
+                        Some(Err(e)) => {
+                            read_failed(send, &e).await;
+                            break;
+                        }
+                        None => break,
+                    },
+                }
             }
+        };
+
+        tokio::pin!(reading, writing);
+        tokio::select! {
+            _ = &mut writing => {}
+            // Stream closed by the client: finish handling replies already read.
+            _ = &mut reading => writing.await,
+        }
+    }
+
+    for task in forwarders {
+        task.abort();
+    }
+}
+
+/// Lease and push, page by page, every stored event of `ns` that `group` has
+/// not settled and nobody holds a lease on. Used when a subscriber lagged.
+async fn push_backlog(
+    send: &mut SendStream,
+    stream_id: u32,
+    queue: &EventQueue,
+    ns: &str,
+    group: &str,
+) -> Result<(), std::io::Error> {
+    let mut after = None;
+    loop {
+        let page = match queue.lease_events(ns, group, after, MAX_FETCH_LIMIT) {
+            Ok(page) => page,
             Err(e) => {
-                eprintln!("❌ Error reading from stream: {:?}", e);
-                break;
+                let message = format!("failed to lease missed events for {ns}: {e}");
+                storage_failure(send, stream_id, stream_id, message).await;
+                return Ok(());
             }
+        };
+        push_events(send, stream_id, page.events).await?;
+        if !page.has_more {
+            return Ok(());
         }
+        after = page.cursor;
     }
 }
 
+/// One event goes out as an Event frame, several as an EventsBatch.
+async fn push_events(send: &mut SendStream, stream_id: u32, mut events: Vec<Event>) -> Result<(), std::io::Error> {
+    let (r#type, payload) = match events.len() {
+        0 => return Ok(()),
+        1 => (FrameType::Event, frame::Payload::Event(events.remove(0))),
+        _ => (FrameType::EventsBatch, frame::Payload::EventsBatch(EventsBatch { events, ..Default::default() })),
+    };
+
+    let pushed = H3XFrame {
+        version: PROTO_VERSION,
+        stream_id,
+        r#type: r#type as i32,
+        payload: Some(payload),
+    };
+    write_frame(send, &pushed).await
+}
+
 pub async fn handle_frame(
     frame: H3XFrame,
     send: &mut SendStream,
     recv: &mut RecvStream,
+    session: &ConnectionSession,
     registry: NamespaceRegistry,
     queue: EventQueue,
 ) -> Result<(), String> {
-    let ft = FrameType::try_from(frame.r#type).map_err(|_| "bad frame type")?;
+    let Ok(ft) = FrameType::try_from(frame.r#type) else {
+        let message = format!("unknown frame type {}", frame.r#type);
+        send_error(send, frame.stream_id, Error::new(ErrorCode::InvalidRequest, message, frame.stream_id)).await;
+        return Ok(());
+    };
+
+    // Hello is read whatever its envelope says; it is where versions are agreed.
+    if ft != FrameType::Hello
+        && let Some(error) = check_protocol(&frame, ft, session).await
+    {
+        send_error(send, frame.stream_id, error).await;
+        return Ok(());
+    }
 
     match ft {
+        FrameType::Hello => handle_hello(frame, send, session).await,
         FrameType::Ping => handle_ping(frame, send).await,
-        FrameType::Auth => handle_auth(frame, registry, send).await,
-        FrameType::Event => handle_event(frame, registry, queue).await,
-        FrameType::FetchEvents => handle_fetch_events(frame, send, recv, &queue.db).await,
-        FrameType::EventsBatch => handle_events_batch(frame, send, registry, queue).await,
-        FrameType::AckEvent => handle_ack_event(frame, &queue.db).await,
+        FrameType::Auth => {
+            if let Some(authed) = handle_auth(frame, registry, send, session.peer_names.as_deref()).await {
+                *session.session.write().await = Some(authed);
+            }
+        }
+        FrameType::Event => handle_event(frame, send, session, registry, queue).await,
+        FrameType::FetchEvents => handle_fetch_events(frame, send, recv, session, registry, &queue).await,
+        FrameType::EventsBatch => handle_events_batch(frame, send, session, registry, queue).await,
+        FrameType::AckEvent => handle_ack_event(frame, send, session, registry, &queue).await,
+        FrameType::Nack => handle_nack(frame, send, session, registry, &queue).await,
+        FrameType::Subscribe => handle_subscribe(frame, send, recv, session, registry, &queue).await,
+        FrameType::Replay => handle_replay(frame, send, session, registry, &queue).await,
         FrameType::Ack => println!("✅ ACK received on stream {}", frame.stream_id),
-        other => eprintln!("❌ Unsupported frame type: {:?}", other),
+        other => {
+            let message = format!("{:?} frames are not accepted by the server", other);
+            send_error(send, frame.stream_id, Error::new(ErrorCode::InvalidRequest, message, frame.stream_id)).await;
+        }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::version::Negotiated;
+    use crate::server::session::new_connection_session;
+
+    fn envelope(version: u32, ft: FrameType) -> H3XFrame {
+        H3XFrame { version, stream_id: 7, r#type: ft as i32, payload: None }
+    }
+
+    #[test]
+    fn fetch_limit_defaults_and_caps() {
+        assert_eq!(fetch_limit(0), DEFAULT_FETCH_LIMIT);
+        assert_eq!(fetch_limit(7), 7);
+        assert_eq!(fetch_limit(1000), 1000);
+        assert_eq!(fetch_limit(5000), MAX_FETCH_LIMIT);
+    }
+
+    #[tokio::test]
+    async fn check_protocol_rejects_versions_other_than_the_negotiated_one() {
+        let session = new_connection_session(None);
+        assert!(check_protocol(&envelope(PROTO_VERSION, FrameType::Ping), FrameType::Ping, &session).await.is_none());
+        let err = check_protocol(&envelope(99, FrameType::Ping), FrameType::Ping, &session).await.unwrap();
+        assert_eq!(err.code(), ErrorCode::BadVersion);
+        assert_eq!(err.supported_versions, SUPPORTED_VERSIONS);
+
+        *session.protocol.write().await = Some(Negotiated { version: 2, capabilities: vec![Capability::Batching] });
+        let err = check_protocol(&envelope(PROTO_VERSION, FrameType::Ping), FrameType::Ping, &session).await.unwrap();
+        assert_eq!(err.code(), ErrorCode::BadVersion, "supported, but not what the connection agreed on");
+        assert_eq!(err.supported_versions, [2]);
+        assert_eq!(err.request_id, "7");
+
+        let batch = envelope(2, FrameType::EventsBatch);
+        assert!(check_protocol(&batch, FrameType::EventsBatch, &session).await.is_none());
+        let subscribe = envelope(2, FrameType::Subscribe);
+        let err = check_protocol(&subscribe, FrameType::Subscribe, &session).await.unwrap();
+        assert_eq!(err.code(), ErrorCode::InvalidRequest, "Subscriptions was not negotiated");
+    }
+}
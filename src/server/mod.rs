@@ -1,44 +1,66 @@
 mod handlers;
+mod session;
+pub mod builder;
+pub mod params;
 
+use anyhow::Context;
 use quinn::{Endpoint, ServerConfig};
-use std::collections::HashMap;
-use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
 
 use std::convert::TryFrom;
 
+use session::new_connection_session;
+use crate::server::params::ServerParams;
 use crate::state::queue::EventQueue;
-use crate::tls::generate_or_load_cert;
-use crate::state::registry::{ClientMetadata, NamespaceRegistry};
-
-use crate::protocol::h3x::{
-    Frame as H3XFrame,
-    FrameType,
-};
-
-pub async fn run_server(client_id: String, token: String, ns: String) {
-    let (cert_chain, key) = generate_or_load_cert();
-    let server_config = ServerConfig::with_single_cert(cert_chain, key).unwrap();
-    let addr: SocketAddr = "127.0.0.1:5000".parse().unwrap();
-    let endpoint = Endpoint::server(server_config, addr).unwrap();
-
-    let event_queue = EventQueue::new("data/event_queue.db")
-        .expect("Failed to initialize event queue");
-
-    let mut registry_map: HashMap<String, ClientMetadata> = HashMap::new();
-    registry_map.insert(
-        format!("client_id:{}", client_id.clone()),
-        ClientMetadata {
-            client_id,
-            token,
-        },
-    );
-
-    // registry setup...
+use crate::tls::{dev_self_signed, load_ca_roots, load_cert_chain, load_private_key, peer_identities, server_config};
+use crate::state::registry::{NamespaceRegistry, RegistryStore};
+
+use crate::protocol::h3x::FrameType;
+
+/// How often the registry file is checked for changes.
+const REGISTRY_POLL: Duration = Duration::from_secs(2);
+/// How often expired leases are found and redelivered.
+const LEASE_CHECK: Duration = Duration::from_secs(1);
+/// How often namespace retention limits are enforced.
+const RETENTION_CHECK: Duration = Duration::from_secs(10);
+/// How often scheduled events are checked and released once due.
+const SCHEDULE_CHECK: Duration = Duration::from_millis(250);
+/// How often expired events are swept.
+const EXPIRY_CHECK: Duration = Duration::from_secs(1);
+/// Largest frame accepted; a bigger one gets an Error and closes its stream.
+const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+/// Serve until the endpoint closes. Fails if TLS, the bind address, the
+/// queue or the registry cannot be set up.
+pub async fn run_server(params: ServerParams) -> anyhow::Result<()> {
+    let server_config = build_server_config(&params).context("TLS setup failed")?;
+    let addr = params.bind_addr;
+    let endpoint = Endpoint::server(server_config, addr).with_context(|| format!("Failed to bind {addr}"))?;
+
+    let event_queue = EventQueue::new(params.queue_path())
+        .context("Failed to initialize event queue")?
+        .with_visibility_timeout(params.visibility_timeout)
+        .with_policies(params.policies.clone());
+    event_queue.spawn_redelivery(LEASE_CHECK);
+    event_queue.spawn_compactor(RETENTION_CHECK);
+    event_queue.spawn_scheduler(SCHEDULE_CHECK);
+    event_queue.spawn_expiry_sweeper(EXPIRY_CHECK);
+
+    let store = RegistryStore::new(&params.registry_path);
+    let registry_map = store.load().context("Failed to load client registry")?;
+    println!("📒 Loaded {} client(s) from {}", registry_map.len(), store.path().display());
+
     let registry: NamespaceRegistry = Arc::new(RwLock::new(registry_map));
+    store.spawn_reloader(registry.clone(), REGISTRY_POLL);
     println!("🚀 Server listening on {}", addr);
 
+    let mtls = params.client_ca_path.is_some();
+    if mtls {
+        println!("🔏 mTLS enabled: client certificates required");
+    }
+
     while let Some(connecting) = endpoint.accept().await {
         let registry = registry.clone();
         let queue = event_queue.clone();
@@ -51,21 +73,40 @@ pub async fn run_server(client_id: String, token: String, ns: String) {
 
             println!("✅ Connection from {}", conn.remote_address());
 
+            // rustls already verified the chain; keep the names for mapping at Auth.
+            let peer_names = if mtls {
+                match peer_identities(&conn) {
+                    Some(names) => Some(names),
+                    None => {
+                        eprintln!("❌ mTLS connection without a usable client certificate");
+                        conn.close(0u32.into(), b"client certificate required");
+                        return;
+                    }
+                }
+            } else {
+                None
+            };
+
+            // Filled by the first successful Auth; every stream on this connection shares it.
+            let session = new_connection_session(peer_names);
+
             loop {
                 match conn.accept_bi().await {
                     Ok((mut send, mut recv)) => {
                         let registry = registry.clone();
                         let queue = queue.clone();
+                        let session = session.clone();
 
                         tokio::spawn(async move {
                             loop {
-                                match H3XFrame::read_from(&mut recv).await {
+                                match handlers::read_frame(&mut recv).await {
                                     Ok(Some(frame)) => {
                                         println!("📦 Frame received: {:?}", FrameType::try_from(frame.r#type));
                                         if let Err(e) = handlers::handle_frame(
                                             frame,
                                             &mut send,
                                             &mut recv,
+                                            &session,
                                             registry.clone(),
                                             queue.clone(),
                                         ).await {
@@ -74,7 +115,7 @@ pub async fn run_server(client_id: String, token: String, ns: String) {
                                         }
                                     }
                                     Ok(None) => { println!("📴 Stream closed by client."); break; }
-                                    Err(e) => { eprintln!("❌ Stream read error: {:?}", e); break; }
+                                    Err(e) => { handlers::read_failed(&mut send, &e).await; break; }
                                 }
                             }
                         });
@@ -84,4 +125,15 @@ pub async fn run_server(client_id: String, token: String, ns: String) {
             }
         });
     }
+    Ok(())
+}
+
+fn build_server_config(params: &ServerParams) -> anyhow::Result<ServerConfig> {
+    let (cert_chain, key) = if params.tls_dev {
+        dev_self_signed(&params.cert_path, &params.key_path)?
+    } else {
+        (load_cert_chain(&params.cert_path)?, load_private_key(&params.key_path)?)
+    };
+    let client_ca = params.client_ca_path.as_deref().map(load_ca_roots).transpose()?;
+    server_config(cert_chain, key, client_ca)
 }
@@ -0,0 +1,31 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::state::policy::Policies;
+
+#[derive(Debug, Clone)]
+pub struct ServerParams {
+    pub bind_addr: SocketAddr,
+    pub data_dir: PathBuf,
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    /// Generate a self-signed certificate when none exists. Never use in production.
+    pub tls_dev: bool,
+    /// CA bundle for client certificates. When set, clients must present a
+    /// certificate signed by it whose name maps to their registry entry (mTLS).
+    pub client_ca_path: Option<PathBuf>,
+    /// JSON client registry; reloaded on change or SIGHUP.
+    pub registry_path: PathBuf,
+    /// How long a delivered event stays invisible before it is redelivered.
+    pub visibility_timeout: Duration,
+    /// Server-wide and per-namespace queue policies (max deliveries, ...).
+    pub policies: Policies,
+}
+
+impl ServerParams {
+    /// Path of the sled event queue inside `data_dir`.
+    pub fn queue_path(&self) -> PathBuf {
+        self.data_dir.join("event_queue.db")
+    }
+}
@@ -0,0 +1,32 @@
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::protocol::version::Negotiated;
+
+/// Identity established by a successful Auth frame on a connection.
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub client_id: String,
+    pub namespaces: Vec<String>,
+}
+
+/// Per-connection state, shared by every stream on that connection.
+#[derive(Debug, Default)]
+pub struct ConnectionState {
+    /// Empty until `handle_auth` succeeds.
+    pub session: RwLock<Option<Session>>,
+    /// Set by `handle_hello`; empty for clients that skip Hello.
+    pub protocol: RwLock<Option<Negotiated>>,
+    /// Names from the verified client certificate when mTLS is on.
+    pub peer_names: Option<Vec<String>>,
+}
+
+pub type ConnectionSession = Arc<ConnectionState>;
+
+pub fn new_connection_session(peer_names: Option<Vec<String>>) -> ConnectionSession {
+    Arc::new(ConnectionState {
+        session: RwLock::new(None),
+        protocol: RwLock::new(None),
+        peer_names,
+    })
+}
@@ -1,2 +1,3 @@
+pub mod policy;
 pub mod queue;
 pub mod registry;
\ No newline at end of file
@@ -0,0 +1,84 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Queue settings for one namespace. Unset fields fall back to the server-wide default.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct NamespacePolicy {
+    /// Failed deliveries (nacks and expired leases) before an event is dead-lettered.
+    /// Unset means retry forever.
+    pub max_deliveries: Option<u32>,
+    /// Retention: drop events older than this (by `Event.timestamp`), acked or not.
+    pub retention_max_age_secs: Option<u64>,
+    /// Retention: drop the oldest events once stored frames exceed this many bytes.
+    pub retention_max_bytes: Option<u64>,
+    /// Retention: keep at most this many events.
+    pub retention_max_count: Option<u64>,
+    /// Expire events this long after they are enqueued, unless they set `expires_at`.
+    pub ttl_secs: Option<u64>,
+    /// Move expired events to each group's dead-letter tree instead of deleting them.
+    pub dead_letter_expired: Option<bool>,
+    /// Drop re-publishes of an event id (or idempotency key) seen within this
+    /// window, acking them with the original id. Unset means no deduplication.
+    pub dedupe_window_secs: Option<u64>,
+}
+
+impl NamespacePolicy {
+    /// Fill unset fields from `fallback`.
+    fn or(&self, fallback: &NamespacePolicy) -> NamespacePolicy {
+        NamespacePolicy {
+            max_deliveries: self.max_deliveries.or(fallback.max_deliveries),
+            retention_max_age_secs: self.retention_max_age_secs.or(fallback.retention_max_age_secs),
+            retention_max_bytes: self.retention_max_bytes.or(fallback.retention_max_bytes),
+            retention_max_count: self.retention_max_count.or(fallback.retention_max_count),
+            ttl_secs: self.ttl_secs.or(fallback.ttl_secs),
+            dead_letter_expired: self.dead_letter_expired.or(fallback.dead_letter_expired),
+            dedupe_window_secs: self.dedupe_window_secs.or(fallback.dedupe_window_secs),
+        }
+    }
+
+    /// Reject settings that are set but meaningless (zero limits, windows and TTLs).
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_deliveries == Some(0) {
+            return Err("max_deliveries must be at least 1".into());
+        }
+        if self.retention_max_age_secs == Some(0) || self.retention_max_count == Some(0) {
+            return Err("Retention max age and max count must be at least 1".into());
+        }
+        if self.ttl_secs == Some(0) {
+            return Err("ttl_secs must be at least 1".into());
+        }
+        if self.dedupe_window_secs == Some(0) {
+            return Err("dedupe_window_secs must be at least 1".into());
+        }
+        Ok(())
+    }
+
+    /// With any retention limit set, acked events are kept until the compactor
+    /// drops them; without, they are deleted once every consumer group acked them.
+    pub fn retains(&self) -> bool {
+        self.retention_max_age_secs.is_some() || self.retention_max_bytes.is_some() || self.retention_max_count.is_some()
+    }
+}
+
+/// Server-wide default plus per-namespace overrides.
+#[derive(Debug, Clone, Default)]
+pub struct Policies {
+    pub default: NamespacePolicy,
+    pub namespaces: HashMap<String, NamespacePolicy>,
+}
+
+impl Policies {
+    /// Validate the default and every per-namespace policy.
+    pub fn validate(&self) -> Result<(), String> {
+        std::iter::once(&self.default)
+            .chain(self.namespaces.values())
+            .try_for_each(NamespacePolicy::validate)
+    }
+
+    pub fn for_namespace(&self, namespace: &str) -> NamespacePolicy {
+        match self.namespaces.get(namespace) {
+            Some(policy) => policy.or(&self.default),
+            None => self.default.clone(),
+        }
+    }
+}
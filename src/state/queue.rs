@@ -1,51 +1,654 @@
-use sled::{Db, IVec, Result, open};
-use std::sync::Arc;
+use sled::transaction::TransactionError;
+use sled::{Db, IVec, Result, Transactional, Tree, open};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use prost::Message;
+use tokio::sync::broadcast;
 
 use crate::protocol::h3x::{
+    DeadLetter,
+    Event,
     Frame as H3XFrame,
+    FrameType,
     frame, // for the oneof
 };
+use crate::protocol::version::PROTO_VERSION;
+use crate::state::policy::{NamespacePolicy, Policies};
+use crate::utils::now_ms;
+
+// Storage layout (single sled db):
+//   tree "{ns}"        : seq (u64 BE, from `generate_id`) -> prost-encoded Event Frame
+//   tree "{ns}.index"  : event_id (UUID string)          -> seq (u64 BE)
+//   tree "{ns}.groups" : consumer group name             -> joined at (unix ms u64 BE)
+//   tree "{ns}.offsets": consumer group name             -> seq (u64 BE); the group settled everything up to it
+//   tree "{ns}.ordering": ordering key, 0xFF, seq (u64 BE) -> empty; stored events that carry an ordering key
+//   tree "{ns}.scheduled": deliver_at (unix ms u64 BE) + event_id -> prost-encoded Event Frame, not yet due
+//   tree "{ns}.expiry" : expires_at (unix ms u64 BE) + seq (u64 BE) -> empty; stored events that can expire
+//   tree "{ns}.dedupe" : idempotency key or event_id      -> first published (unix ms u64 BE) + event_id
+// and per consumer group, suffixed "#{group}" except for the default group:
+//   tree "{ns}.leases" : seq (u64 BE)                    -> `Lease` (expiry unix ms u64 BE + kind byte)
+//   tree "{ns}.attempts": seq (u64 BE)                   -> failed delivery attempts (u32 BE)
+//   tree "{ns}.failures": seq (u64 BE)                   -> recent failure reasons (JSON string array)
+//   tree "{ns}.dlq"    : seq (u64 BE)                    -> prost-encoded `DeadLetter`
+//   tree "{ns}.acks"   : seq (u64 BE)                    -> empty; settled out of order, past the offset
+// Keys in the namespace tree sort in enqueue order, and every group is handed
+// events in that order. Every reader/writer goes through `EventQueue`; nothing
+// else should touch these trees directly.
+// Events with the same ordering key are strictly sequential per group: one is
+// delivered only once every earlier one with that key is settled.
+// Events with a future `deliver_at` (or a `delay_ms`) wait in "{ns}.scheduled"
+// and are enqueued, getting their seq, when `release_scheduled` finds them due.
+// Events past their `expires_at` (or the namespace TTL) are never delivered;
+// `expire_events` deletes them, or dead-letters them for every group that has
+// not settled them when the namespace policy asks for it.
+// With a dedupe window, `enqueue_once` drops a publish whose dedupe key was
+// already published within the window and reports the original event id.
+// Each group sees every event once; members of a group compete for its leases.
+// A leased event is in flight: invisible to the group until acked or the lease expires.
+// A nacked event holds a requeue lease instead, so it stays hidden for its delay.
+// Once an event fails `max_deliveries` times it moves to the group's dead-letter tree.
+// A group's offset moves forward over its settled (acked or dead-lettered) events.
+// Without retention limits an event is deleted once every joined group settled it;
+// with them, events stay until `compact` drops them by age, size or count.
+const INDEX_SUFFIX: &str = ".index";
+const LEASES_SUFFIX: &str = ".leases";
+const ATTEMPTS_SUFFIX: &str = ".attempts";
+const FAILURES_SUFFIX: &str = ".failures";
+const DLQ_SUFFIX: &str = ".dlq";
+const ACKS_SUFFIX: &str = ".acks";
+const GROUPS_SUFFIX: &str = ".groups";
+const OFFSETS_SUFFIX: &str = ".offsets";
+const ORDERING_SUFFIX: &str = ".ordering";
+const SCHEDULED_SUFFIX: &str = ".scheduled";
+const EXPIRY_SUFFIX: &str = ".expiry";
+const DEDUPE_SUFFIX: &str = ".dedupe";
+
+/// Event metadata entry naming its ordering key.
+pub const ORDERING_KEY: &str = "ordering_key";
+
+/// Event metadata entry to deduplicate publishes on instead of the event id.
+pub const IDEMPOTENCY_KEY: &str = "idempotency_key";
+
+/// Separates the ordering key from the seq in "{ns}.ordering"; never valid UTF-8.
+const ORDERING_SEPARATOR: u8 = 0xFF;
+
+/// Separates a group name from the tree suffix for non-default groups.
+const GROUP_SEPARATOR: char = '#';
+
+/// Group used by consumers that do not name one.
+pub const DEFAULT_GROUP: &str = "default";
+
+/// Longest accepted consumer group name.
+const MAX_GROUP_LEN: usize = 64;
+
+/// Longest accepted namespace name, in bytes.
+const MAX_NAMESPACE_LEN: usize = 128;
+
+/// Prefix sled reserves for its own trees.
+const SLED_RESERVED_PREFIX: &str = "__sled__";
+
+/// Failure reasons kept per event (oldest dropped first).
+const MAX_FAILURE_REASONS: usize = 10;
+
+/// Default time a delivered event stays invisible waiting for its ack.
+pub const DEFAULT_VISIBILITY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Events buffered per namespace for live subscribers. A subscriber that falls
+/// further behind than this skips ahead; the events stay stored for fetch.
+const SUBSCRIBER_BUFFER: usize = 1024;
 
 #[derive(Clone)]
 pub struct EventQueue {
     pub db: Arc<Db>,
+    /// One broadcast channel per namespace, created on first subscribe.
+    subscribers: Arc<Mutex<HashMap<String, broadcast::Sender<Event>>>>,
+    visibility_timeout: Duration,
+    policies: Arc<Policies>,
+}
+
+/// What happened to an event after a failed delivery.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureOutcome {
+    /// Will be delivered again; carries the failed-attempt count so far.
+    Requeued(u32),
+    /// Hit the namespace's `max_deliveries` and moved to `{ns}.dlq`.
+    DeadLettered(u32),
+}
+
+/// What `enqueue_once` did with a published event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Enqueued {
+    /// Stored (with its seq) or scheduled (`None`), as `enqueue` reports it.
+    Stored(Option<u64>),
+    /// Already published within the dedupe window; carries the original event id.
+    Duplicate(String),
+}
+
+/// One `lease_events` call's worth of events.
+#[derive(Debug, Default)]
+pub struct EventPage {
+    pub events: Vec<Event>,
+    /// Last position up to which every scanned event is settled for the
+    /// group; continue after it. Events delivered in this page or skipped
+    /// (leased to another member, waiting on their ordering key) stay ahead
+    /// of it, so a continued scan sees them again once they are free.
+    pub cursor: Option<u64>,
+    /// The page filled up before every stored event was scanned.
+    pub has_more: bool,
+}
+
+/// Which part of a namespace's retained history `EventQueue::history` reads.
+/// `None` bounds are open.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HistoryRange {
+    /// First offset (sequence) to include.
+    pub from_offset: Option<u64>,
+    /// Earliest `Event.timestamp` to include (Unix seconds).
+    pub from_timestamp: Option<i64>,
+    /// `Event.timestamp` to stop before (Unix seconds, exclusive).
+    pub to_timestamp: Option<i64>,
+}
+
+impl HistoryRange {
+    fn contains(&self, timestamp: i64) -> bool {
+        self.from_timestamp.is_none_or(|from| timestamp >= from)
+            && self.to_timestamp.is_none_or(|to| timestamp < to)
+    }
+}
+
+/// Namespace names are 1–128 bytes without `.` or `#`, which separate the
+/// internal tree suffixes and group names, so no namespace can name another
+/// namespace's tree. sled's own `__sled__` prefix is reserved too.
+pub fn valid_namespace(namespace: &str) -> bool {
+    (1..=MAX_NAMESPACE_LEN).contains(&namespace.len())
+        && !namespace.contains(['.', GROUP_SEPARATOR])
+        && !namespace.starts_with(SLED_RESERVED_PREFIX)
+}
+
+fn check_namespace(namespace: &str) -> Result<()> {
+    if valid_namespace(namespace) {
+        Ok(())
+    } else {
+        Err(sled::Error::Unsupported(format!("invalid namespace {namespace:?}")))
+    }
+}
+
+/// Group names are 1–64 ASCII letters, digits, `-` or `_`.
+pub fn valid_group(group: &str) -> bool {
+    (1..=MAX_GROUP_LEN).contains(&group.len())
+        && group.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// Name of a per-group tree. The default group keeps the unsuffixed name
+/// used before groups existed.
+fn group_tree_name(namespace: &str, suffix: &str, group: &str) -> String {
+    if group == DEFAULT_GROUP {
+        format!("{namespace}{suffix}")
+    } else {
+        format!("{namespace}{suffix}{GROUP_SEPARATOR}{group}")
+    }
+}
+
+/// Inverse of `group_tree_name`: `(namespace, group)` for a tree with `suffix`.
+fn parse_group_tree_name<'a>(name: &'a str, suffix: &str) -> Option<(&'a str, &'a str)> {
+    let (namespace, rest) = name.rsplit_once(suffix)?;
+    if rest.is_empty() {
+        return Some((namespace, DEFAULT_GROUP));
+    }
+    Some((namespace, rest.strip_prefix(GROUP_SEPARATOR)?))
+}
+
+/// Opaque cursor string handed to clients for a queue position.
+pub fn encode_cursor(seq: u64) -> String {
+    format!("{seq:016x}")
+}
+
+/// Inverse of `encode_cursor`; `None` for anything it did not produce.
+pub fn decode_cursor(cursor: &str) -> Option<u64> {
+    if cursor.len() != 16 {
+        return None;
+    }
+    u64::from_str_radix(cursor, 16).ok()
 }
 
 impl EventQueue {
-    pub fn new(path: &str) -> Result<Self> {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
         let db = open(path)?;
-        Ok(Self { db: Arc::new(db) })
+        let queue = Self {
+            db: Arc::new(db),
+            subscribers: Arc::new(Mutex::new(HashMap::new())),
+            visibility_timeout: DEFAULT_VISIBILITY_TIMEOUT,
+            policies: Arc::new(Policies::default()),
+        };
+
+        let migrated = queue.migrate_legacy()?;
+        if migrated > 0 {
+            println!("🔧 Migrated {migrated} legacy event(s) into namespace trees");
+        }
+
+        Ok(queue)
+    }
+
+    /// How long a delivered event stays leased before it is redelivered.
+    pub fn with_visibility_timeout(mut self, timeout: Duration) -> Self {
+        self.visibility_timeout = timeout;
+        self
+    }
+
+    /// Per-namespace settings such as `max_deliveries`.
+    pub fn with_policies(mut self, policies: Policies) -> Self {
+        self.policies = Arc::new(policies);
+        self
+    }
+
+    fn tree(&self, namespace: &str) -> Result<Tree> {
+        self.db.open_tree(namespace)
+    }
+
+    fn index(&self, namespace: &str) -> Result<Tree> {
+        self.db.open_tree(format!("{namespace}{INDEX_SUFFIX}"))
+    }
+
+    fn leases(&self, namespace: &str, group: &str) -> Result<Tree> {
+        self.db.open_tree(group_tree_name(namespace, LEASES_SUFFIX, group))
+    }
+
+    fn attempts(&self, namespace: &str, group: &str) -> Result<Tree> {
+        self.db.open_tree(group_tree_name(namespace, ATTEMPTS_SUFFIX, group))
+    }
+
+    fn failures(&self, namespace: &str, group: &str) -> Result<Tree> {
+        self.db.open_tree(group_tree_name(namespace, FAILURES_SUFFIX, group))
+    }
+
+    fn dlq(&self, namespace: &str, group: &str) -> Result<Tree> {
+        self.db.open_tree(group_tree_name(namespace, DLQ_SUFFIX, group))
+    }
+
+    fn acks(&self, namespace: &str, group: &str) -> Result<Tree> {
+        self.db.open_tree(group_tree_name(namespace, ACKS_SUFFIX, group))
     }
 
-    /// Enqueue a single Event frame into its namespace tree.
+    fn offsets(&self, namespace: &str) -> Result<Tree> {
+        self.db.open_tree(format!("{namespace}{OFFSETS_SUFFIX}"))
+    }
+
+    fn ordering(&self, namespace: &str) -> Result<Tree> {
+        self.db.open_tree(format!("{namespace}{ORDERING_SUFFIX}"))
+    }
+
+    fn scheduled(&self, namespace: &str) -> Result<Tree> {
+        self.db.open_tree(format!("{namespace}{SCHEDULED_SUFFIX}"))
+    }
+
+    fn expiry(&self, namespace: &str) -> Result<Tree> {
+        self.db.open_tree(format!("{namespace}{EXPIRY_SUFFIX}"))
+    }
+
+    fn dedupe(&self, namespace: &str) -> Result<Tree> {
+        self.db.open_tree(format!("{namespace}{DEDUPE_SUFFIX}"))
+    }
+
+    fn group_registry(&self, namespace: &str) -> Result<Tree> {
+        self.db.open_tree(format!("{namespace}{GROUPS_SUFFIX}"))
+    }
+
+    /// Register `group` as a consumer of `namespace`. From then on events are
+    /// kept until the group is done with them. Returns whether it was new.
+    pub fn join_group(&self, namespace: &str, group: &str) -> Result<bool> {
+        let joined = self
+            .group_registry(namespace)?
+            .compare_and_swap(group.as_bytes(), None::<&[u8]>, Some(&now_ms().to_be_bytes()))?;
+        Ok(joined.is_ok())
+    }
+
+    /// Highest position up to which `group` has settled every event, if any.
+    pub fn offset_of(&self, namespace: &str, group: &str) -> Result<Option<u64>> {
+        Ok(self.offsets(namespace)?.get(group.as_bytes())?.as_deref().and_then(decode_seq))
+    }
+
+    /// Move `group`'s offset over every settled event directly after it,
+    /// dropping their out-of-order ack marks. Returns the new offset.
+    fn advance_offset(&self, namespace: &str, group: &str) -> Result<Option<u64>> {
+        let tree = self.tree(namespace)?;
+        let acks = self.acks(namespace, group)?;
+        let start = self.offset_of(namespace, group)?;
+        let mut offset = start;
+
+        for res in tree.range(start.map_or(0, |seq| seq.saturating_add(1)).to_be_bytes()..) {
+            let (seq, _) = res?;
+            if acks.remove(&seq)?.is_none() {
+                break;
+            }
+            offset = decode_seq(&seq);
+        }
+
+        if offset != start
+            && let Some(new) = offset
+        {
+            // Concurrent advances may finish out of order; never move backwards.
+            self.offsets(namespace)?.fetch_and_update(group.as_bytes(), |old| {
+                let old = old.and_then(decode_seq).unwrap_or(0);
+                Some(old.max(new).to_be_bytes().to_vec())
+            })?;
+        }
+        Ok(offset)
+    }
+
+    /// After `group` settled `seqs`: move its offset, without retention
+    /// delete what every group is done with, and hand subscribers the next
+    /// event of each ordering key that was waiting on one of them.
+    fn after_settle(&self, namespace: &str, group: &str, seqs: &[IVec]) -> Result<()> {
+        let tree = self.tree(namespace)?;
+        let mut keyed = Vec::new();
+        for seq in seqs {
+            if let Some(ev) = tree.get(seq)?.as_deref().and_then(decode_event)
+                && let Some(key) = ordering_key(&ev)
+            {
+                keyed.push((key.to_string(), seq));
+            }
+        }
+
+        self.advance_offset(namespace, group)?;
+        if !self.policies.for_namespace(namespace).retains() {
+            self.collect(namespace, seqs)?;
+        }
+
+        for (key, seq) in keyed {
+            self.release_next_in_order(namespace, group, &key, seq)?;
+        }
+        Ok(())
+    }
+
+    /// Notify subscribers of the first event after `seq` with ordering key
+    /// `key` that `group` has not settled, now that it may be delivered.
+    fn release_next_in_order(&self, namespace: &str, group: &str, key: &str, seq: &[u8]) -> Result<()> {
+        let Some(next) = decode_seq(seq).and_then(|s| s.checked_add(1)) else {
+            return Ok(());
+        };
+        let acks = self.acks(namespace, group)?;
+        let tree = self.tree(namespace)?;
+
+        for res in self.ordering(namespace)?.range(ordering_entry(key, next)..=ordering_entry(key, u64::MAX)) {
+            let (entry, _) = res?;
+            let waiting = &entry[entry.len() - 8..];
+            if acks.contains_key(waiting)? {
+                continue;
+            }
+            if let Some(ev) = tree.get(waiting)?.as_deref().and_then(|v| decode_event_at(waiting, v)) {
+                self.notify(&ev);
+            }
+            break;
+        }
+        Ok(())
+    }
+
+    /// Whether an earlier event with `ev`'s ordering key, stored at or after
+    /// `from`, is still unsettled for the group owning `acks`.
+    fn waits_in_order(&self, namespace: &str, ev: &Event, seq: &[u8], acks: &Tree, from: u64) -> Result<bool> {
+        let (Some(key), Some(seq)) = (ordering_key(ev), decode_seq(seq)) else {
+            return Ok(false);
+        };
+        if from >= seq {
+            return Ok(false);
+        }
+
+        for res in self.ordering(namespace)?.range(ordering_entry(key, from)..ordering_entry(key, seq)) {
+            let (entry, _) = res?;
+            if !acks.contains_key(&entry[entry.len() - 8..])? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Consumer groups that joined `namespace`.
+    pub fn groups(&self, namespace: &str) -> Result<Vec<String>> {
+        let mut out = Vec::new();
+        for res in self.group_registry(namespace)?.iter() {
+            let (k, _) = res?;
+            out.push(String::from_utf8_lossy(&k).into_owned());
+        }
+        Ok(out)
+    }
+
+    /// Enqueue a single Event frame into its namespace tree and index its id.
     /// Expects `frame.payload` to be `Some(frame::Payload::Event(_))`.
-    pub fn enqueue(&self, frame: &H3XFrame) -> Result<()> {
-        let ns = match &frame.payload {
-            Some(frame::Payload::Event(ev)) => &ev.namespace,
+    /// Returns the sequence number the event was stored under, or `None` if
+    /// it is not an Event or was scheduled for later delivery.
+    pub fn enqueue(&self, frame: &H3XFrame) -> Result<Option<u64>> {
+        let ev = match &frame.payload {
+            Some(frame::Payload::Event(ev)) => ev,
             _ => {
-                // You can choose to return an error instead; using sled::Error here would be awkward,
-                // so we no-op with Ok(()) to keep the signature. Change if you prefer strictness.
                 eprintln!("enqueue: skipping non-Event frame");
-                return Ok(());
+                return Ok(None);
             }
         };
+        check_namespace(&ev.namespace)?;
+
+        let now = now_ms();
+        let due = match (u64::try_from(ev.deliver_at).unwrap_or(0), ev.delay_ms) {
+            (0, 0) => 0,
+            (0, delay) => now.saturating_add(delay),
+            (at, _) => at,
+        };
+        if due > now {
+            self.schedule(frame, ev, due)?;
+            return Ok(None);
+        }
+
+        // Without its own expiry the event gets the namespace TTL, counted from now.
+        let stamped = match self.policies.for_namespace(&ev.namespace).ttl_secs {
+            Some(secs) if ev.expires_at == 0 => Some(Event {
+                expires_at: now.saturating_add(secs.saturating_mul(1000)) as i64,
+                ..ev.clone()
+            }),
+            _ => None,
+        };
+        let value = match &stamped {
+            Some(event) => H3XFrame { payload: Some(frame::Payload::Event(event.clone())), ..frame.clone() }.encode_to_vec(),
+            None => frame.encode_to_vec(),
+        };
+        let ev = stamped.as_ref().unwrap_or(ev);
+
+        let tree = self.tree(&ev.namespace)?;
+        let index = self.index(&ev.namespace)?;
+        let ordering = self.ordering(&ev.namespace)?;
+        let expiry = self.expiry(&ev.namespace)?;
+        let seq = self.db.generate_id()?;
+
+        let replaced = (&tree, &index, &ordering, &expiry)
+            .transaction(|(tree, index, ordering, expiry)| {
+                // Re-publishing an id replaces the previous copy instead of duplicating it.
+                let old = index.insert(ev.id.as_bytes(), &seq.to_be_bytes())?;
+                if let Some(old) = &old
+                    && let Some(previous) = tree.remove(old)?
+                    && let Some(previous) = decode_event(&previous)
+                    && let Some(old) = decode_seq(old)
+                {
+                    if let Some(key) = ordering_key(&previous) {
+                        ordering.remove(ordering_entry(key, old))?;
+                    }
+                    if let Some(at) = expires_at(&previous) {
+                        expiry.remove(&expiry_entry(at, old))?;
+                    }
+                }
+                tree.insert(&seq.to_be_bytes(), value.as_slice())?;
+                if let Some(key) = ordering_key(ev) {
+                    ordering.insert(ordering_entry(key, seq), &[])?;
+                }
+                if let Some(at) = expires_at(ev) {
+                    expiry.insert(&expiry_entry(at, seq), &[])?;
+                }
+                Ok(old)
+            })
+            .map_err(flatten_tx)?;
+
+        if let Some(old) = replaced {
+            self.forget(&ev.namespace, &old)?;
+        }
+        self.notify(&Event { offset: seq, ..ev.clone() });
+        Ok(Some(seq))
+    }
+
+    /// Enqueue a published Event frame unless its dedupe key (`idempotency_key`
+    /// metadata, else the event id) was published within the namespace's
+    /// dedupe window. Without a window this is just `enqueue`.
+    pub fn enqueue_once(&self, frame: &H3XFrame) -> Result<Enqueued> {
+        let Some(frame::Payload::Event(ev)) = &frame.payload else {
+            return self.enqueue(frame).map(Enqueued::Stored);
+        };
+        let Some(window) = self.policies.for_namespace(&ev.namespace).dedupe_window_secs else {
+            return self.enqueue(frame).map(Enqueued::Stored);
+        };
+        check_namespace(&ev.namespace)?;
+
+        let dedupe = self.dedupe(&ev.namespace)?;
+        let key = dedupe_key(ev);
+        let now = now_ms();
+        let mut record = now.to_be_bytes().to_vec();
+        record.extend_from_slice(ev.id.as_bytes());
+
+        // Claim the key first so concurrent re-publishes cannot both get through.
+        loop {
+            let current = dedupe.get(key)?;
+            if let Some((seen, original)) = current.as_deref().and_then(decode_dedupe)
+                && seen.saturating_add(window.saturating_mul(1000)) > now
+            {
+                return Ok(Enqueued::Duplicate(original));
+            }
+            if dedupe.compare_and_swap(key, current, Some(record.as_slice()))?.is_ok() {
+                break;
+            }
+        }
+
+        match self.enqueue(frame) {
+            Ok(seq) => Ok(Enqueued::Stored(seq)),
+            Err(e) => {
+                // Not stored, so a retry must not be taken for a duplicate.
+                dedupe.compare_and_swap(key, Some(record.as_slice()), None::<&[u8]>)?.ok();
+                Err(e)
+            }
+        }
+    }
+
+    /// Drop dedupe records older than their namespace's window.
+    /// Returns the number of records dropped.
+    pub fn prune_dedupe(&self) -> Result<usize> {
+        let now = now_ms();
+        let mut pruned = 0;
 
-        let tree = self.db.open_tree(ns)?;
-        let id = self.db.generate_id()?;
-        let value = frame.encode_to_vec();
-        tree.insert(id.to_be_bytes(), value)?;
+        for name in self.db.tree_names() {
+            let Some(namespace) = std::str::from_utf8(&name).ok().and_then(|n| n.strip_suffix(DEDUPE_SUFFIX)) else {
+                continue;
+            };
+            // A namespace whose window was turned off keeps nothing.
+            let window = self.policies.for_namespace(namespace).dedupe_window_secs.unwrap_or(0);
+            let dedupe = self.dedupe(namespace)?;
+
+            for res in dedupe.iter() {
+                let (key, record) = res?;
+                let stale = decode_dedupe(&record)
+                    .is_none_or(|(seen, _)| seen.saturating_add(window.saturating_mul(1000)) <= now);
+                if stale && dedupe.compare_and_swap(&key, Some(record), None::<&[u8]>)?.is_ok() {
+                    pruned += 1;
+                }
+            }
+        }
+
+        Ok(pruned)
+    }
+
+    /// Hold `ev` back in "{ns}.scheduled" until `due` (unix ms).
+    fn schedule(&self, frame: &H3XFrame, ev: &Event, due: u64) -> Result<()> {
+        let mut key = due.to_be_bytes().to_vec();
+        key.extend_from_slice(ev.id.as_bytes());
+
+        // Store the resolved time so releasing it does not apply the delay again.
+        let event = Event { deliver_at: due as i64, delay_ms: 0, ..ev.clone() };
+        let stored = H3XFrame { payload: Some(frame::Payload::Event(event)), ..frame.clone() };
+        self.scheduled(&ev.namespace)?.insert(key, stored.encode_to_vec())?;
+        println!("🗓️ Event {} in {} scheduled for {due} ms", ev.id, ev.namespace);
         Ok(())
     }
 
+    /// Enqueue every scheduled event that has come due, earliest first.
+    /// Returns the number of events released.
+    pub fn release_scheduled(&self) -> Result<usize> {
+        let now = now_ms();
+        let mut released = 0;
+
+        for name in self.db.tree_names() {
+            let Some(namespace) = std::str::from_utf8(&name).ok().and_then(|n| n.strip_suffix(SCHEDULED_SUFFIX)) else {
+                continue;
+            };
+            let scheduled = self.scheduled(namespace)?;
+
+            for res in scheduled.range(..now.saturating_add(1).to_be_bytes()) {
+                let (key, v) = res?;
+                match decode_stored_frame(&v) {
+                    Some(stored) => {
+                        self.enqueue(&stored)?;
+                        released += 1;
+                    }
+                    None => eprintln!("schedule: failed to decode scheduled entry in {namespace}, dropping"),
+                }
+                scheduled.remove(key)?;
+            }
+        }
+
+        Ok(released)
+    }
+
+    /// Run `release_scheduled` every `every` until the task is dropped.
+    pub fn spawn_scheduler(&self, every: Duration) -> tokio::task::JoinHandle<()> {
+        let queue = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(every);
+            loop {
+                ticker.tick().await;
+                match queue.release_scheduled() {
+                    Ok(0) => {}
+                    Ok(n) => println!("⏱️ Released {n} scheduled event(s)"),
+                    Err(e) => eprintln!("❌ Releasing scheduled events failed: {e}"),
+                }
+            }
+        })
+    }
+
+    /// Receive every event committed to `namespace` from now on.
+    pub fn subscribe(&self, namespace: &str) -> broadcast::Receiver<Event> {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers
+            .entry(namespace.to_string())
+            .or_insert_with(|| broadcast::channel(SUBSCRIBER_BUFFER).0)
+            .subscribe()
+    }
+
+    fn notify(&self, ev: &Event) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if let Some(tx) = subscribers.get(&ev.namespace)
+            && tx.send(ev.clone()).is_err()
+        {
+            // Last subscriber went away; drop the channel until someone subscribes again.
+            subscribers.remove(&ev.namespace);
+        }
+    }
+
     /// Fetch up to `max` frames from a namespace, decoding each prost Frame.
     /// Returns owned data to avoid lifetime issues with sled iterators.
     pub fn fetch(&self, namespace: &str, max: Option<usize>) -> Result<Vec<(u64, H3XFrame)>> {
-        let tree = self.db.open_tree(namespace)?;
+        let tree = self.tree(namespace)?;
         let iter = tree.iter();
         let mut out = Vec::new();
 
         for res in iter {
+            if max.is_some_and(|limit| out.len() >= limit) {
+                break;
+            }
+
             let (k, v) = match res {
                 Ok(kv) => kv,
                 Err(e) => {
@@ -54,30 +657,1396 @@ impl EventQueue {
                 }
             };
 
-            if k.len() != 8 {
+            let Some(id) = decode_seq(&k) else {
                 eprintln!("fetch: bad key length {}, skipping", k.len());
                 continue;
-            }
+            };
 
-            let id = u64::from_be_bytes(k.as_ref().try_into().unwrap());
             match H3XFrame::decode(v.as_ref()) {
                 Ok(frame) => out.push((id, frame)),
                 Err(e) => eprintln!("fetch: failed to decode prost Frame: {e}"),
             }
+        }
+
+        Ok(out)
+    }
+
+    /// Fetch up to `max` Events from a namespace in enqueue order.
+    pub fn fetch_events(&self, namespace: &str, max: Option<usize>) -> Result<Vec<Event>> {
+        let frames = self.fetch(namespace, max)?;
+        Ok(frames
+            .into_iter()
+            .filter_map(|(_, frame)| match frame.payload {
+                Some(frame::Payload::Event(ev)) => Some(ev),
+                _ => {
+                    eprintln!("fetch_events: stored frame without Event payload");
+                    None
+                }
+            })
+            .collect())
+    }
 
-            if let Some(limit) = max {
-                if out.len() >= limit {
-                    break;
+    /// Look up the sequence number an event id was stored under.
+    pub fn seq_of(&self, namespace: &str, event_id: &str) -> Result<Option<u64>> {
+        let index = self.index(namespace)?;
+        Ok(index.get(event_id.as_bytes())?.as_deref().and_then(decode_seq))
+    }
+
+    /// Acknowledge an event by id for `group`. Returns `true` if it was pending.
+    pub fn ack(&self, namespace: &str, group: &str, event_id: &str) -> Result<bool> {
+        let (acked, _) = self.ack_many(namespace, group, &[event_id])?;
+        Ok(!acked.is_empty())
+    }
+
+    /// Acknowledge several events of one namespace for `group` in a single
+    /// transaction, then delete those every group is done with.
+    /// Returns `(acked, not_found)` ids; already-acked ids count as not found.
+    pub fn ack_many<S: AsRef<str>>(
+        &self,
+        namespace: &str,
+        group: &str,
+        event_ids: &[S],
+    ) -> Result<(Vec<String>, Vec<String>)> {
+        // The acking group must count when deciding whether an event can go.
+        self.join_group(namespace, group)?;
+        let index = self.index(namespace)?;
+        let leases = self.leases(namespace, group)?;
+        let attempts = self.attempts(namespace, group)?;
+        let failures = self.failures(namespace, group)?;
+        let acks = self.acks(namespace, group)?;
+        let offsets = self.offsets(namespace)?;
+
+        let (acked, not_found, seqs) = (&index, &leases, &attempts, &failures, &acks, &offsets)
+            .transaction(|(index, leases, attempts, failures, acks, offsets)| {
+                let offset = offsets.get(group.as_bytes())?.as_deref().and_then(decode_seq);
+                let mut acked = Vec::new();
+                let mut not_found = Vec::new();
+                let mut seqs = Vec::new();
+
+                for id in event_ids.iter().map(AsRef::as_ref) {
+                    let Some(seq) = index.get(id.as_bytes())? else {
+                        not_found.push(id.to_string());
+                        continue;
+                    };
+                    if decode_seq(&seq) <= offset || acks.insert(&seq, &[])?.is_some() {
+                        not_found.push(id.to_string());
+                        continue;
+                    }
+                    leases.remove(&seq)?;
+                    attempts.remove(&seq)?;
+                    failures.remove(&seq)?;
+                    acked.push(id.to_string());
+                    seqs.push(seq);
                 }
+
+                Ok((acked, not_found, seqs))
+            })
+            .map_err(flatten_tx)?;
+
+        self.after_settle(namespace, group, &seqs)?;
+        Ok((acked, not_found))
+    }
+
+    /// Delete the events in `seqs` that every joined group settled (acked or
+    /// dead-lettered). Returns how many were deleted.
+    fn collect(&self, namespace: &str, seqs: &[IVec]) -> Result<usize> {
+        let mut groups = Vec::new();
+        for group in self.groups(namespace)? {
+            groups.push((self.acks(namespace, &group)?, self.offset_of(namespace, &group)?));
+        }
+        let mut settled = Vec::new();
+
+        for seq in seqs {
+            let mut done = true;
+            for (acks, offset) in &groups {
+                done &= decode_seq(seq) <= *offset || acks.contains_key(seq)?;
+            }
+            if done {
+                settled.push(seq.clone());
             }
         }
 
-        Ok(out)
+        self.delete_stored(namespace, &settled)
     }
 
-    /// Remove a stored frame by numeric ID from a namespace tree.
-    pub fn remove(&self, namespace: &str, id: u64) -> Result<Option<IVec>> {
-        let tree = self.db.open_tree(namespace)?;
-        tree.remove(id.to_be_bytes())
+    /// Delete stored events with their index, ordering and expiry entries and
+    /// every group's state for them, in one transaction so a crash never leaves
+    /// entries pointing at a deleted event. Returns how many were deleted.
+    fn delete_stored(&self, namespace: &str, seqs: &[IVec]) -> Result<usize> {
+        if seqs.is_empty() {
+            return Ok(0);
+        }
+        let mut trees = vec![
+            self.tree(namespace)?,
+            self.index(namespace)?,
+            self.ordering(namespace)?,
+            self.expiry(namespace)?,
+        ];
+        for group in self.groups(namespace)? {
+            trees.push(self.leases(namespace, &group)?);
+            trees.push(self.attempts(namespace, &group)?);
+            trees.push(self.failures(namespace, &group)?);
+            trees.push(self.acks(namespace, &group)?);
+        }
+
+        trees
+            .as_slice()
+            .transaction(|trees| {
+                let [tree, index, ordering, expiry, group_state @ ..] = trees.as_slice() else {
+                    return Ok(0);
+                };
+                let mut deleted = 0;
+
+                for seq in seqs {
+                    let Some(removed) = tree.remove(seq)? else {
+                        continue;
+                    };
+                    deleted += 1;
+                    if let Some(ev) = decode_event(&removed)
+                        && let Some(position) = decode_seq(seq)
+                    {
+                        // Only drop the index entry if it still points here, not at a re-publish.
+                        if index.get(ev.id.as_bytes())?.as_deref() == Some(seq.as_ref()) {
+                            index.remove(ev.id.as_bytes())?;
+                        }
+                        if let Some(key) = ordering_key(&ev) {
+                            ordering.remove(ordering_entry(key, position))?;
+                        }
+                        if let Some(at) = expires_at(&ev) {
+                            expiry.remove(&expiry_entry(at, position))?;
+                        }
+                    }
+                    for state in group_state {
+                        state.remove(seq)?;
+                    }
+                }
+
+                Ok(deleted)
+            })
+            .map_err(flatten_tx)
+    }
+
+    /// Drop the ordering and expiry entries of a stored event that is going away.
+    fn unindex(&self, namespace: &str, ev: &Event, seq: &[u8]) -> Result<()> {
+        let Some(seq) = decode_seq(seq) else {
+            return Ok(());
+        };
+        if let Some(key) = ordering_key(ev) {
+            self.ordering(namespace)?.remove(ordering_entry(key, seq))?;
+        }
+        if let Some(at) = expires_at(ev) {
+            self.expiry(namespace)?.remove(expiry_entry(at, seq))?;
+        }
+        Ok(())
+    }
+
+    /// Drop every group's delivery state for `seq`.
+    fn forget(&self, namespace: &str, seq: &[u8]) -> Result<()> {
+        for group in self.groups(namespace)? {
+            self.leases(namespace, &group)?.remove(seq)?;
+            self.attempts(namespace, &group)?.remove(seq)?;
+            self.failures(namespace, &group)?.remove(seq)?;
+            self.acks(namespace, &group)?.remove(seq)?;
+        }
+        Ok(())
+    }
+
+    /// Negative ack: count a failed attempt and hide the event for `requeue_delay`,
+    /// after which it is redelivered, or dead-letter it once the namespace's
+    /// `max_deliveries` is reached. Only events `group` holds leased or
+    /// requeued count. Returns `None` if the event is gone, settled or not
+    /// leased by the group.
+    pub fn nack(
+        &self,
+        namespace: &str,
+        group: &str,
+        event_id: &str,
+        reason: &str,
+        requeue_delay: Duration,
+    ) -> Result<Option<FailureOutcome>> {
+        let Some(seq) = self.index(namespace)?.get(event_id.as_bytes())? else {
+            return Ok(None);
+        };
+        let requeue = Lease {
+            until: now_ms() + requeue_delay.as_millis() as u64,
+            requeued: true,
+        };
+        self.record_failure(namespace, group, &seq, reason, Some(requeue))
+    }
+
+    /// Count one failed delivery of `seq` to `group` and keep its reason. Under
+    /// the limit the event gets `requeue` (if any) as its lease; at the limit it
+    /// is moved to the group's dead-letter tree with every recorded reason.
+    /// `None` if the event is gone, the group is already done with it, or, for
+    /// a nack (`requeue` set), the group holds no lease on it.
+    fn record_failure(
+        &self,
+        namespace: &str,
+        group: &str,
+        seq: &[u8],
+        reason: &str,
+        requeue: Option<Lease>,
+    ) -> Result<Option<FailureOutcome>> {
+        self.join_group(namespace, group)?;
+        let max_deliveries = self.policies.for_namespace(namespace).max_deliveries;
+        let tree = self.tree(namespace)?;
+        let leases = self.leases(namespace, group)?;
+        let attempts = self.attempts(namespace, group)?;
+        let failures = self.failures(namespace, group)?;
+        let dlq = self.dlq(namespace, group)?;
+        let acks = self.acks(namespace, group)?;
+        let offsets = self.offsets(namespace)?;
+
+        let outcome = (&tree, &leases, &attempts, &failures, &dlq, &acks, &offsets)
+            .transaction(|(tree, leases, attempts, failures, dlq, acks, offsets)| {
+                let Some(stored) = tree.get(seq)? else {
+                    return Ok(None);
+                };
+                let offset = offsets.get(group.as_bytes())?.as_deref().and_then(decode_seq);
+                if decode_seq(seq) <= offset || acks.get(seq)?.is_some() {
+                    return Ok(None);
+                }
+                // A nack must come from the group holding the event, not any reader.
+                if requeue.is_some() && leases.get(seq)?.is_none() {
+                    return Ok(None);
+                }
+
+                let count = bump_attempts(attempts.get(seq)?.as_deref());
+                let mut reasons = decode_reasons(failures.get(seq)?.as_deref());
+                reasons.push(reason.to_string());
+                if reasons.len() > MAX_FAILURE_REASONS {
+                    reasons.drain(..reasons.len() - MAX_FAILURE_REASONS);
+                }
+
+                if max_deliveries.is_none_or(|max| count < max) {
+                    attempts.insert(seq, &count.to_be_bytes())?;
+                    failures.insert(seq, encode_reasons(&reasons))?;
+                    if let Some(lease) = requeue {
+                        leases.insert(seq, lease.encode().as_slice())?;
+                    }
+                    return Ok(Some(FailureOutcome::Requeued(count)));
+                }
+
+                // Undecodable entries cannot be redelivered either; keep them out of the way.
+                if let Some(event) = decode_event(&stored) {
+                    let letter = DeadLetter {
+                        event: Some(event),
+                        attempts: count,
+                        reasons,
+                        dead_at_ms: now_ms(),
+                    };
+                    dlq.insert(seq, letter.encode_to_vec())?;
+                }
+                acks.insert(seq, &[])?;
+                leases.remove(seq)?;
+                attempts.remove(seq)?;
+                failures.remove(seq)?;
+                Ok(Some(FailureOutcome::DeadLettered(count)))
+            })
+            .map_err(flatten_tx)?;
+
+        if let Some(FailureOutcome::DeadLettered(_)) = outcome {
+            self.after_settle(namespace, group, &[IVec::from(seq)])?;
+        }
+        Ok(outcome)
+    }
+
+    /// Failed delivery attempts (nacks and expired leases) `group` recorded for an event.
+    pub fn attempts_of(&self, namespace: &str, group: &str, event_id: &str) -> Result<u32> {
+        let Some(seq) = self.index(namespace)?.get(event_id.as_bytes())? else {
+            return Ok(0);
+        };
+        Ok(decode_attempts(self.attempts(namespace, group)?.get(seq)?.as_deref()))
+    }
+
+    /// Deliver up to `max` events from a namespace that `group` has not
+    /// finished, in enqueue order, starting after the `after` position and
+    /// leasing each for the visibility timeout. Leased events are skipped by
+    /// the group's other fetches until acked or the lease expires.
+    pub fn lease_events(&self, namespace: &str, group: &str, after: Option<u64>, max: usize) -> Result<EventPage> {
+        let tree = self.tree(namespace)?;
+        let leases = self.leases(namespace, group)?;
+        let acks = self.acks(namespace, group)?;
+        // Everything up to the group's offset is settled; start past it.
+        let settled = self.offset_of(namespace, group)?.map_or(0, |seq| seq.saturating_add(1));
+        let from = after.map_or(0, |seq| seq.saturating_add(1)).max(settled);
+        let now = now_ms();
+        let mut page = EventPage { cursor: after, ..EventPage::default() };
+        // The cursor only moves over a run of settled events.
+        let mut settled_run = true;
+
+        for res in tree.range(from.to_be_bytes()..) {
+            let (k, v) = match res {
+                Ok(kv) => kv,
+                Err(e) => {
+                    eprintln!("lease: sled iter error: {e}");
+                    continue;
+                }
+            };
+
+            if page.events.len() >= max {
+                page.has_more = true;
+                break;
+            }
+
+            let Some(ev) = decode_event_at(&k, &v) else {
+                eprintln!("lease: stored frame without Event payload, skipping");
+                continue;
+            };
+
+            if acks.contains_key(&k)? {
+                if settled_run {
+                    page.cursor = decode_seq(&k).or(page.cursor);
+                }
+                continue;
+            }
+            settled_run = false;
+
+            // Expired events wait for `expire_events`; they are never delivered.
+            if !is_expired(&ev, now)
+                && !self.waits_in_order(namespace, &ev, &k, &acks, settled)?
+                && self.try_lease(&leases, &k)?
+            {
+                page.events.push(ev);
+            }
+        }
+
+        Ok(page)
+    }
+
+    /// Read up to `max` retained events of a namespace within `range`, in
+    /// offset order, starting after the `after` position. Nothing is leased or
+    /// acked, so this works for any group and does not affect delivery.
+    pub fn history(&self, namespace: &str, range: &HistoryRange, after: Option<u64>, max: usize) -> Result<EventPage> {
+        let tree = self.tree(namespace)?;
+        let from = after
+            .map(|seq| seq.saturating_add(1))
+            .max(range.from_offset)
+            .unwrap_or(0);
+        let mut page = EventPage { cursor: after, ..EventPage::default() };
+
+        for res in tree.range(from.to_be_bytes()..) {
+            let (k, v) = res?;
+            if page.events.len() >= max {
+                page.has_more = true;
+                break;
+            }
+            page.cursor = decode_seq(&k).or(page.cursor);
+
+            // Timestamps come from publishers and need not be monotonic, so scan rather than seek.
+            if let Some(ev) = decode_event_at(&k, &v)
+                && range.contains(ev.timestamp)
+            {
+                page.events.push(ev);
+            }
+        }
+
+        Ok(page)
+    }
+
+    /// Lease one event by id for `group`, e.g. before pushing it to a subscriber.
+    /// Returns `false` if it is gone, the group is done with it, another
+    /// member of the group holds the lease, or an earlier event with the same
+    /// ordering key is still unsettled, or it has expired.
+    pub fn lease(&self, namespace: &str, group: &str, event_id: &str) -> Result<bool> {
+        let Some(seq) = self.index(namespace)?.get(event_id.as_bytes())? else {
+            return Ok(false);
+        };
+        let Some(ev) = self.tree(namespace)?.get(&seq)?.as_deref().and_then(decode_event) else {
+            return Ok(false);
+        };
+        if is_expired(&ev, now_ms()) {
+            return Ok(false);
+        }
+        let offset = self.offset_of(namespace, group)?;
+        let acks = self.acks(namespace, group)?;
+        if decode_seq(&seq) <= offset || acks.contains_key(&seq)? {
+            return Ok(false);
+        }
+        let settled = offset.map_or(0, |seq| seq.saturating_add(1));
+        if self.waits_in_order(namespace, &ev, &seq, &acks, settled)? {
+            return Ok(false);
+        }
+        self.try_lease(&self.leases(namespace, group)?, &seq)
+    }
+
+    /// Take the lease on `seq` unless someone holds an unexpired one.
+    /// Compare-and-swap, so concurrent consumers never both win.
+    fn try_lease(&self, leases: &Tree, seq: &[u8]) -> Result<bool> {
+        let now = now_ms();
+        let current = leases.get(seq)?;
+        if current.as_deref().and_then(Lease::decode).is_some_and(|lease| lease.until > now) {
+            return Ok(false);
+        }
+
+        let lease = Lease {
+            until: now + self.visibility_timeout.as_millis() as u64,
+            requeued: false,
+        };
+        Ok(leases
+            .compare_and_swap(seq, current, Some(lease.encode().as_slice()))?
+            .is_ok())
+    }
+
+    /// Drop every lease that has expired and re-broadcast its event so
+    /// subscribers get it again. An expired delivery lease (no ack, no nack)
+    /// counts as a failed attempt. Returns the number of redelivered events.
+    pub fn expire_leases(&self) -> Result<usize> {
+        let now = now_ms();
+        let mut redelivered = 0;
+
+        for name in self.db.tree_names() {
+            let Some((namespace, group)) = std::str::from_utf8(&name)
+                .ok()
+                .and_then(|n| parse_group_tree_name(n, LEASES_SUFFIX))
+            else {
+                continue;
+            };
+
+            let leases = self.leases(namespace, group)?;
+            let tree = self.tree(namespace)?;
+
+            for res in leases.iter() {
+                let (seq, raw) = res?;
+                let lease = Lease::decode(&raw);
+                if lease.is_some_and(|l| l.until > now) {
+                    continue;
+                }
+                // Lost a race with a fresh lease or an ack; leave it to them.
+                if leases.compare_and_swap(&seq, Some(&raw), None::<&[u8]>)?.is_err() {
+                    continue;
+                }
+
+                let Some(ev) = tree.get(&seq)?.as_deref().and_then(|v| decode_event_at(&seq, v)) else {
+                    continue;
+                };
+
+                if lease.is_some_and(|l| !l.requeued)
+                    && let Some(FailureOutcome::DeadLettered(n)) =
+                        self.record_failure(namespace, group, &seq, "visibility timeout expired", None)?
+                {
+                    println!("🪦 Event {} in {} dead-lettered for group {group} after {n} failed deliveries", ev.id, namespace);
+                    continue;
+                }
+
+                println!("⏰ Lease expired for event {} in {} (group {group}), redelivering", ev.id, namespace);
+                self.notify(&ev);
+                redelivered += 1;
+            }
+        }
+
+        Ok(redelivered)
+    }
+
+    /// Run `expire_leases` every `every` until the task is dropped.
+    pub fn spawn_redelivery(&self, every: Duration) -> tokio::task::JoinHandle<()> {
+        let queue = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(every);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = queue.expire_leases() {
+                    eprintln!("❌ Lease expiry failed: {e}");
+                }
+            }
+        })
+    }
+
+    /// Remove every stored event whose `expires_at` has passed: delete it, or
+    /// with `dead_letter_expired` dead-letter it for each group that has not
+    /// settled it. Returns the number of expired events.
+    pub fn expire_events(&self) -> Result<usize> {
+        let now = now_ms();
+        let mut expired = 0;
+
+        for name in self.db.tree_names() {
+            let Some(namespace) = std::str::from_utf8(&name).ok().and_then(|n| n.strip_suffix(EXPIRY_SUFFIX)) else {
+                continue;
+            };
+            let dead_letter = self.policies.for_namespace(namespace).dead_letter_expired.unwrap_or(false);
+            let expiry = self.expiry(namespace)?;
+            let mut n = 0;
+
+            for res in expiry.range(..now.saturating_add(1).to_be_bytes()) {
+                let (entry, _) = res?;
+                let seq = IVec::from(&entry[8..]);
+                let gone = if dead_letter {
+                    self.dead_letter_expired(namespace, &seq)?
+                } else {
+                    self.delete_expired(namespace, &seq)?
+                };
+                expiry.remove(&entry)?;
+                n += usize::from(gone);
+            }
+
+            if n > 0 {
+                println!("⌛ Expired {n} event(s) in {namespace}");
+            }
+            expired += n;
+        }
+
+        Ok(expired)
+    }
+
+    /// Delete an expired event, then let the next event of its ordering key through.
+    fn delete_expired(&self, namespace: &str, seq: &IVec) -> Result<bool> {
+        let key = self
+            .tree(namespace)?
+            .get(seq)?
+            .as_deref()
+            .and_then(decode_event)
+            .and_then(|ev| ordering_key(&ev).map(str::to_string));
+        if self.delete_stored(namespace, std::slice::from_ref(seq))? == 0 {
+            return Ok(false);
+        }
+
+        if let Some(key) = key {
+            for group in self.groups(namespace)? {
+                self.release_next_in_order(namespace, &group, &key, seq)?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Dead-letter an expired event for every group still waiting on it (the
+    /// default group if none joined yet). Returns whether any group had it.
+    fn dead_letter_expired(&self, namespace: &str, seq: &IVec) -> Result<bool> {
+        let mut groups = self.groups(namespace)?;
+        if groups.is_empty() {
+            groups.push(DEFAULT_GROUP.to_string());
+        }
+        let mut buried = false;
+
+        for group in groups {
+            self.join_group(namespace, &group)?;
+            let tree = self.tree(namespace)?;
+            let leases = self.leases(namespace, &group)?;
+            let attempts = self.attempts(namespace, &group)?;
+            let failures = self.failures(namespace, &group)?;
+            let dlq = self.dlq(namespace, &group)?;
+            let acks = self.acks(namespace, &group)?;
+            let offsets = self.offsets(namespace)?;
+
+            let dead = (&tree, &leases, &attempts, &failures, &dlq, &acks, &offsets)
+                .transaction(|(tree, leases, attempts, failures, dlq, acks, offsets)| {
+                    let Some(event) = tree.get(seq)?.as_deref().and_then(decode_event) else {
+                        return Ok(false);
+                    };
+                    let offset = offsets.get(group.as_bytes())?.as_deref().and_then(decode_seq);
+                    if decode_seq(seq) <= offset || acks.get(seq)?.is_some() {
+                        return Ok(false);
+                    }
+
+                    let mut reasons = decode_reasons(failures.get(seq)?.as_deref());
+                    reasons.push(format!("expired at {} ms", event.expires_at));
+                    let letter = DeadLetter {
+                        event: Some(event),
+                        attempts: decode_attempts(attempts.get(seq)?.as_deref()),
+                        reasons,
+                        dead_at_ms: now_ms(),
+                    };
+                    dlq.insert(seq, letter.encode_to_vec())?;
+                    acks.insert(seq, &[])?;
+                    leases.remove(seq)?;
+                    attempts.remove(seq)?;
+                    failures.remove(seq)?;
+                    Ok(true)
+                })
+                .map_err(flatten_tx)?;
+
+            if dead {
+                println!("🪦 Expired event in {namespace} dead-lettered for group {group}");
+                self.after_settle(namespace, &group, std::slice::from_ref(seq))?;
+                buried = true;
+            }
+        }
+
+        Ok(buried)
+    }
+
+    /// Run `expire_events` every `every` until the task is dropped.
+    pub fn spawn_expiry_sweeper(&self, every: Duration) -> tokio::task::JoinHandle<()> {
+        let queue = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(every);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = queue.expire_events() {
+                    eprintln!("❌ Expiry sweep failed: {e}");
+                }
+            }
+        })
+    }
+
+    /// Enforce every namespace's retention limits, dropping the oldest events
+    /// (acked or not) first. Returns the number of events dropped.
+    pub fn compact(&self) -> Result<usize> {
+        let mut dropped = 0;
+
+        for name in self.db.tree_names() {
+            let Some(namespace) = std::str::from_utf8(&name).ok().and_then(|n| n.strip_suffix(INDEX_SUFFIX)) else {
+                continue;
+            };
+            let policy = self.policies.for_namespace(namespace);
+            if !policy.retains() {
+                continue;
+            }
+
+            let n = self.compact_namespace(namespace, &policy)?;
+            if n > 0 {
+                println!("🗜️ Retention dropped {n} event(s) from {namespace}");
+            }
+            dropped += n;
+        }
+
+        Ok(dropped)
+    }
+
+    fn compact_namespace(&self, namespace: &str, policy: &NamespacePolicy) -> Result<usize> {
+        let tree = self.tree(namespace)?;
+        let mut count = tree.len() as u64;
+        let mut bytes = match policy.retention_max_bytes {
+            Some(_) => tree.iter().values().try_fold(0u64, |sum, v| v.map(|v| sum + v.len() as u64))?,
+            None => 0,
+        };
+        let cutoff = policy
+            .retention_max_age_secs
+            .map(|age| (now_ms() / 1000).saturating_sub(age) as i64);
+        let mut dropped = 0;
+
+        for res in tree.iter() {
+            let (seq, v) = res?;
+            let over_count = policy.retention_max_count.is_some_and(|max| count > max);
+            let over_bytes = policy.retention_max_bytes.is_some_and(|max| bytes > max);
+            // Unset (0) timestamps cannot age out.
+            let too_old = cutoff.is_some_and(|cutoff| {
+                decode_event(&v).is_some_and(|ev| ev.timestamp > 0 && ev.timestamp < cutoff)
+            });
+            if !(over_count || over_bytes || too_old) {
+                break;
+            }
+
+            dropped += self.delete_stored(namespace, std::slice::from_ref(&seq))?;
+            count -= 1;
+            bytes = bytes.saturating_sub(v.len() as u64);
+        }
+
+        Ok(dropped)
+    }
+
+    /// Run `compact` and `prune_dedupe` every `every` until the task is dropped.
+    pub fn spawn_compactor(&self, every: Duration) -> tokio::task::JoinHandle<()> {
+        let queue = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(every);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = queue.compact() {
+                    eprintln!("❌ Retention compaction failed: {e}");
+                }
+                if let Err(e) = queue.prune_dedupe() {
+                    eprintln!("❌ Pruning dedupe records failed: {e}");
+                }
+            }
+        })
+    }
+
+    /// Events `group` dead-lettered in a namespace, oldest first.
+    pub fn dead_letters(&self, namespace: &str, group: &str) -> Result<Vec<DeadLetter>> {
+        let mut out = Vec::new();
+        for res in self.dlq(namespace, group)?.iter() {
+            let (_, v) = res?;
+            match DeadLetter::decode(v.as_ref()) {
+                Ok(letter) => out.push(letter),
+                Err(e) => eprintln!("dlq: failed to decode dead letter: {e}"),
+            }
+        }
+        Ok(out)
+    }
+
+    /// Hand `group`'s dead-lettered events back to it with a clean attempt
+    /// count: just `event_id`, or all of them when `None`. Other groups are not
+    /// redelivered to. Returns how many.
+    pub fn replay_dead_letters(&self, namespace: &str, group: &str, event_id: Option<&str>) -> Result<usize> {
+        let dlq = self.dlq(namespace, group)?;
+        let acks = self.acks(namespace, group)?;
+        let tree = self.tree(namespace)?;
+        let offset = self.offset_of(namespace, group)?;
+        let mut replayed = 0;
+
+        for (seq, letter) in self.matching_dead_letters(namespace, group, event_id)? {
+            let Some(event) = letter.event else {
+                continue;
+            };
+
+            if decode_seq(&seq) > offset && tree.contains_key(&seq)? {
+                // Other groups still hold it: just make it pending for this group again.
+                acks.remove(&seq)?;
+                dlq.remove(&seq)?;
+                self.notify(&Event { offset: decode_seq(&seq).unwrap_or(0), ..event });
+                replayed += 1;
+                continue;
+            }
+
+            let frame = H3XFrame {
+                version: PROTO_VERSION,
+                stream_id: 0,
+                r#type: FrameType::Event as i32,
+                payload: Some(frame::Payload::Event(event.clone())),
+            };
+            let Some(new_seq) = self.enqueue(&frame)? else {
+                continue;
+            };
+            for other in self.groups(namespace)?.iter().filter(|g| *g != group) {
+                self.acks(namespace, other)?.insert(new_seq.to_be_bytes(), &[])?;
+            }
+            dlq.remove(seq)?;
+            replayed += 1;
+        }
+
+        Ok(replayed)
+    }
+
+    /// Delete `group`'s dead-lettered events: just `event_id`, or all of them when `None`.
+    pub fn purge_dead_letters(&self, namespace: &str, group: &str, event_id: Option<&str>) -> Result<usize> {
+        let dlq = self.dlq(namespace, group)?;
+        let matching = self.matching_dead_letters(namespace, group, event_id)?;
+        for (seq, _) in &matching {
+            dlq.remove(seq)?;
+        }
+        Ok(matching.len())
+    }
+
+    fn matching_dead_letters(
+        &self,
+        namespace: &str,
+        group: &str,
+        event_id: Option<&str>,
+    ) -> Result<Vec<(IVec, DeadLetter)>> {
+        let mut out = Vec::new();
+        for res in self.dlq(namespace, group)?.iter() {
+            let (seq, v) = res?;
+            let Ok(letter) = DeadLetter::decode(v.as_ref()) else {
+                continue;
+            };
+            let id = letter.event.as_ref().map(|ev| ev.id.as_str());
+            if event_id.is_none() || id == event_id {
+                out.push((seq, letter));
+            }
+        }
+        Ok(out)
+    }
+
+    /// Remove a stored frame by numeric ID from a namespace tree.
+    pub fn remove(&self, namespace: &str, id: u64) -> Result<Option<IVec>> {
+        let tree = self.tree(namespace)?;
+        let removed = tree.remove(id.to_be_bytes())?;
+        self.forget(namespace, &id.to_be_bytes())?;
+
+        if let Some(frame::Payload::Event(ev)) =
+            removed.as_deref().and_then(decode_stored_frame).and_then(|f| f.payload)
+        {
+            self.index(namespace)?.remove(ev.id.as_bytes())?;
+            self.unindex(namespace, &ev, &id.to_be_bytes())?;
+        }
+
+        Ok(removed)
+    }
+
+    /// Rewrite legacy `"{ns}:{uuid}"` entries from the default tree into the
+    /// namespace trees. Legacy entries are migrated in timestamp order, since
+    /// UUID keys carry no ordering. Entries whose namespace is not valid stay
+    /// in the default tree. Returns the number of migrated events.
+    pub fn migrate_legacy(&self) -> Result<usize> {
+        let mut legacy: Vec<(IVec, H3XFrame, i64)> = Vec::new();
+
+        for res in self.db.iter() {
+            let (k, v) = res?;
+            let Some((ns, id)) = std::str::from_utf8(&k).ok().and_then(|s| s.split_once(':')) else {
+                continue;
+            };
+
+            let Some(stored) = decode_stored_frame(&v) else {
+                eprintln!("migrate: failed to decode legacy entry {ns}:{id}, leaving in place");
+                continue;
+            };
+            if FrameType::try_from(stored.r#type) != Ok(FrameType::Event) {
+                continue;
+            }
+            let ts = match &stored.payload {
+                Some(frame::Payload::Event(ev)) if valid_namespace(&ev.namespace) => ev.timestamp,
+                Some(frame::Payload::Event(ev)) => {
+                    eprintln!("migrate: legacy entry {ns}:{id} has invalid namespace {:?}, leaving in place", ev.namespace);
+                    continue;
+                }
+                _ => continue,
+            };
+
+            legacy.push((k, stored, ts));
+        }
+
+        legacy.sort_by_key(|(_, _, ts)| *ts);
+
+        for (key, stored, _) in &legacy {
+            self.enqueue(stored)?;
+            self.db.remove(key)?;
+        }
+
+        if !legacy.is_empty() {
+            self.db.flush()?;
+        }
+
+        Ok(legacy.len())
+    }
+}
+
+/// Decode a stored frame. Legacy writers used either raw `encode` or
+/// `encode_length_delimited`, so try both.
+fn decode_stored_frame(bytes: &[u8]) -> Option<H3XFrame> {
+    H3XFrame::decode(bytes)
+        .ok()
+        .or_else(|| H3XFrame::decode_length_delimited(bytes).ok())
+}
+
+/// Value of a `{ns}.leases` tree.
+#[derive(Debug, Clone, Copy)]
+struct Lease {
+    /// Unix ms when the event becomes visible again.
+    until: u64,
+    /// Set by `nack`: a requeue delay rather than an in-flight delivery.
+    requeued: bool,
+}
+
+impl Lease {
+    fn encode(&self) -> [u8; 9] {
+        let mut out = [0u8; 9];
+        out[..8].copy_from_slice(&self.until.to_be_bytes());
+        out[8] = self.requeued as u8;
+        out
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let (until, kind) = bytes.split_at_checked(8)?;
+        Some(Self {
+            until: decode_seq(until)?,
+            requeued: kind.first() == Some(&1),
+        })
+    }
+}
+
+fn decode_reasons(bytes: Option<&[u8]>) -> Vec<String> {
+    bytes
+        .and_then(|b| serde_json::from_slice(b).ok())
+        .unwrap_or_default()
+}
+
+fn encode_reasons(reasons: &[String]) -> Vec<u8> {
+    serde_json::to_vec(reasons).unwrap_or_default()
+}
+
+/// A stored `{ns}.attempts` counter; missing means no failed attempts yet.
+fn decode_attempts(bytes: Option<&[u8]>) -> u32 {
+    bytes
+        .and_then(|b| b.try_into().ok())
+        .map(u32::from_be_bytes)
+        .unwrap_or(0)
+}
+
+fn bump_attempts(old: Option<&[u8]>) -> u32 {
+    decode_attempts(old).saturating_add(1)
+}
+
+fn decode_event(bytes: &[u8]) -> Option<Event> {
+    match H3XFrame::decode(bytes).ok()?.payload {
+        Some(frame::Payload::Event(ev)) => Some(ev),
+        _ => None,
+    }
+}
+
+/// The event's ordering key, if it has a non-empty one.
+fn ordering_key(ev: &Event) -> Option<&str> {
+    ev.metadata.get(ORDERING_KEY).map(String::as_str).filter(|key| !key.is_empty())
+}
+
+/// "{ns}.ordering" key for the event at `seq` with ordering key `key`.
+fn ordering_entry(key: &str, seq: u64) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(key.len() + 9);
+    entry.extend_from_slice(key.as_bytes());
+    entry.push(ORDERING_SEPARATOR);
+    entry.extend_from_slice(&seq.to_be_bytes());
+    entry
+}
+
+/// Key an event is deduplicated on: its idempotency key if set, else its id.
+fn dedupe_key(ev: &Event) -> &str {
+    ev.metadata
+        .get(IDEMPOTENCY_KEY)
+        .map(String::as_str)
+        .filter(|key| !key.is_empty())
+        .unwrap_or(&ev.id)
+}
+
+/// A "{ns}.dedupe" value: when the key was first published, and by which event id.
+fn decode_dedupe(bytes: &[u8]) -> Option<(u64, String)> {
+    let (seen, id) = bytes.split_first_chunk::<8>()?;
+    Some((u64::from_be_bytes(*seen), String::from_utf8_lossy(id).into_owned()))
+}
+
+/// When the event expires (unix ms), if it does.
+fn expires_at(ev: &Event) -> Option<u64> {
+    u64::try_from(ev.expires_at).ok().filter(|at| *at > 0)
+}
+
+fn is_expired(ev: &Event, now: u64) -> bool {
+    expires_at(ev).is_some_and(|at| at <= now)
+}
+
+/// "{ns}.expiry" key for the event at `seq` expiring at `at`.
+fn expiry_entry(at: u64, seq: u64) -> [u8; 16] {
+    let mut entry = [0u8; 16];
+    entry[..8].copy_from_slice(&at.to_be_bytes());
+    entry[8..].copy_from_slice(&seq.to_be_bytes());
+    entry
+}
+
+/// Decode a stored event and stamp it with its offset `seq` for delivery.
+fn decode_event_at(seq: &[u8], bytes: &[u8]) -> Option<Event> {
+    let mut ev = decode_event(bytes)?;
+    ev.offset = decode_seq(seq)?;
+    Some(ev)
+}
+
+fn decode_seq(bytes: &[u8]) -> Option<u64> {
+    bytes.try_into().ok().map(u64::from_be_bytes)
+}
+
+fn flatten_tx(e: TransactionError<sled::Error>) -> sled::Error {
+    match e {
+        TransactionError::Abort(e) | TransactionError::Storage(e) => e,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use uuid::Uuid;
+
+    const NS: &str = "orders";
+
+    /// A new event in `NS`.
+    fn event() -> Event {
+        Event { id: Uuid::new_v4().to_string(), namespace: NS.into(), ..Event::default() }
+    }
+
+    fn event_frame(ev: Event) -> H3XFrame {
+        H3XFrame {
+            version: PROTO_VERSION,
+            stream_id: 0,
+            r#type: FrameType::Event as i32,
+            payload: Some(frame::Payload::Event(ev)),
+        }
+    }
+
+    /// A queue in a fresh temp directory, removed again on drop.
+    struct TestQueue {
+        queue: EventQueue,
+        dir: PathBuf,
+    }
+
+    impl TestQueue {
+        fn new() -> Self {
+            Self::with(|queue| queue)
+        }
+
+        /// A queue set up by `configure`, e.g. with policies.
+        fn with(configure: impl FnOnce(EventQueue) -> EventQueue) -> Self {
+            let dir = std::env::temp_dir().join(format!("h3x-queue-test-{}", Uuid::new_v4()));
+            Self { queue: configure(EventQueue::new(&dir).unwrap()), dir }
+        }
+
+        /// A queue whose default policy is `policy`.
+        fn with_policy(policy: NamespacePolicy) -> Self {
+            Self::with(|queue| queue.with_policies(Policies { default: policy, ..Policies::default() }))
+        }
+
+        /// Enqueue `ev` and return its id.
+        fn store(&self, ev: Event) -> String {
+            let id = ev.id.clone();
+            self.queue.enqueue(&event_frame(ev)).unwrap().expect("stored");
+            id
+        }
+
+        /// Enqueue `count` events into `NS` and return their ids in order.
+        fn publish(&self, count: usize) -> Vec<String> {
+            (0..count).map(|_| self.store(event())).collect()
+        }
+
+        fn lease_ids(&self, group: &str) -> Vec<String> {
+            let page = self.queue.lease_events(NS, group, None, 100).unwrap();
+            page.events.into_iter().map(|ev| ev.id).collect()
+        }
+    }
+
+    impl Drop for TestQueue {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[test]
+    fn migration_leaves_invalid_namespaces_in_place() {
+        let q = TestQueue::new();
+        let legacy = |ns: &str| {
+            let ev = Event { namespace: ns.into(), ..event() };
+            let key = format!("{ns}:{}", ev.id);
+            q.queue.db.insert(&key, event_frame(ev).encode_to_vec()).unwrap();
+            key
+        };
+        let valid = legacy(NS);
+        let invalid = [legacy("orders.dlq"), legacy("a#b"), legacy("__sled__x"), legacy(&"n".repeat(200))];
+
+        assert_eq!(q.queue.migrate_legacy().unwrap(), 1);
+        assert_eq!(q.queue.fetch_events(NS, None).unwrap().len(), 1);
+        assert!(q.queue.db.get(&valid).unwrap().is_none());
+        for key in &invalid {
+            assert!(q.queue.db.get(key).unwrap().is_some(), "{key} should stay in the default tree");
+        }
+    }
+
+    #[test]
+    fn every_group_receives_every_event() {
+        let q = TestQueue::new();
+        q.queue.join_group(NS, "a").unwrap();
+        q.queue.join_group(NS, "b").unwrap();
+        let ids = q.publish(3);
+
+        assert_eq!(q.lease_ids("a"), ids);
+        assert_eq!(q.lease_ids("b"), ids);
+    }
+
+    #[test]
+    fn members_of_a_group_compete_for_leases() {
+        let q = TestQueue::new();
+        q.queue.join_group(NS, "a").unwrap();
+        let ids = q.publish(2);
+
+        assert_eq!(q.lease_ids("a"), ids);
+        assert!(q.lease_ids("a").is_empty(), "a second member must not lease in-flight events");
+        assert!(!q.queue.lease(NS, "a", &ids[0]).unwrap());
+    }
+
+    #[test]
+    fn out_of_order_ack_advances_offset_once_the_gap_is_acked() {
+        let q = TestQueue::new();
+        // A second group keeps acked events stored, so the offset walks over them.
+        q.queue.join_group(NS, "a").unwrap();
+        q.queue.join_group(NS, "b").unwrap();
+        let ids = q.publish(3);
+        let seqs: Vec<u64> = ids.iter().map(|id| q.queue.seq_of(NS, id).unwrap().unwrap()).collect();
+        q.lease_ids("a");
+
+        assert!(q.queue.ack(NS, "a", &ids[1]).unwrap());
+        assert_eq!(q.queue.offset_of(NS, "a").unwrap(), None);
+
+        assert!(q.queue.ack(NS, "a", &ids[0]).unwrap());
+        assert_eq!(q.queue.offset_of(NS, "a").unwrap(), Some(seqs[1]));
+
+        assert!(q.queue.ack(NS, "a", &ids[2]).unwrap());
+        assert_eq!(q.queue.offset_of(NS, "a").unwrap(), Some(seqs[2]));
+    }
+
+    #[test]
+    fn event_is_deleted_once_every_group_settled_it() {
+        let q = TestQueue::new();
+        q.queue.join_group(NS, "a").unwrap();
+        q.queue.join_group(NS, "b").unwrap();
+        let ids = q.publish(1);
+
+        assert!(q.queue.ack(NS, "a", &ids[0]).unwrap());
+        assert!(q.queue.seq_of(NS, &ids[0]).unwrap().is_some(), "group b has not settled it yet");
+        assert_eq!(q.queue.fetch_events(NS, None).unwrap().len(), 1);
+
+        assert!(q.queue.ack(NS, "b", &ids[0]).unwrap());
+        assert!(q.queue.seq_of(NS, &ids[0]).unwrap().is_none());
+        assert!(q.queue.fetch_events(NS, None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn expired_lease_is_redelivered() {
+        let q = TestQueue::with(|queue| queue.with_visibility_timeout(Duration::from_millis(50)));
+        let mut pushed = q.queue.subscribe(NS);
+        let ids = q.publish(2);
+        while pushed.try_recv().is_ok() {}
+
+        assert_eq!(q.lease_ids(DEFAULT_GROUP), ids);
+        assert!(q.lease_ids(DEFAULT_GROUP).is_empty(), "leased events are invisible");
+        assert!(q.queue.ack(NS, DEFAULT_GROUP, &ids[1]).unwrap());
+        assert_eq!(q.queue.expire_leases().unwrap(), 0, "the lease has not run out yet");
+
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(q.queue.expire_leases().unwrap(), 1, "only the unacked event comes back");
+        assert_eq!(pushed.try_recv().unwrap().id, ids[0]);
+        assert_eq!(q.queue.attempts_of(NS, DEFAULT_GROUP, &ids[0]).unwrap(), 1);
+        assert_eq!(q.lease_ids(DEFAULT_GROUP), ids[..1]);
+    }
+
+    #[test]
+    fn nack_counts_an_attempt_and_hides_the_event_for_the_delay() {
+        let q = TestQueue::new();
+        let ids = q.publish(1);
+        let nack = |delay| q.queue.nack(NS, DEFAULT_GROUP, &ids[0], "boom", delay).unwrap();
+        assert_eq!(nack(Duration::ZERO), None, "not leased by the group");
+
+        assert_eq!(q.lease_ids(DEFAULT_GROUP), ids);
+        assert_eq!(nack(Duration::from_millis(50)), Some(FailureOutcome::Requeued(1)));
+        assert_eq!(q.queue.attempts_of(NS, DEFAULT_GROUP, &ids[0]).unwrap(), 1);
+        assert!(q.lease_ids(DEFAULT_GROUP).is_empty(), "hidden during the requeue delay");
+
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(q.lease_ids(DEFAULT_GROUP), ids);
+        assert_eq!(nack(Duration::ZERO), Some(FailureOutcome::Requeued(2)));
+
+        assert!(q.queue.ack(NS, DEFAULT_GROUP, &ids[0]).unwrap());
+        assert_eq!(nack(Duration::ZERO), None, "settled events cannot be nacked");
+    }
+
+    #[test]
+    fn dead_letters_after_max_deliveries_and_replays_or_purges_them() {
+        let q = TestQueue::with_policy(NamespacePolicy { max_deliveries: Some(2), ..NamespacePolicy::default() });
+        let ids = q.publish(2);
+        let fail_twice = |id: &str| {
+            for attempt in 1..=2 {
+                assert!(q.queue.lease(NS, DEFAULT_GROUP, id).unwrap());
+                let outcome = q.queue.nack(NS, DEFAULT_GROUP, id, &format!("try {attempt}"), Duration::ZERO);
+                let expected = if attempt < 2 { FailureOutcome::Requeued(1) } else { FailureOutcome::DeadLettered(2) };
+                assert_eq!(outcome.unwrap(), Some(expected));
+            }
+        };
+        fail_twice(&ids[0]);
+        fail_twice(&ids[1]);
+
+        let letters = q.queue.dead_letters(NS, DEFAULT_GROUP).unwrap();
+        assert_eq!(letters.len(), 2);
+        assert_eq!(letters[0].event.as_ref().unwrap().id, ids[0]);
+        assert_eq!(letters[0].attempts, 2);
+        assert_eq!(letters[0].reasons, ["try 1", "try 2"]);
+        assert!(q.lease_ids(DEFAULT_GROUP).is_empty(), "dead-lettered events count as settled");
+
+        assert_eq!(q.queue.replay_dead_letters(NS, DEFAULT_GROUP, Some(&ids[0])).unwrap(), 1);
+        assert_eq!(q.lease_ids(DEFAULT_GROUP), ids[..1]);
+        assert_eq!(q.queue.attempts_of(NS, DEFAULT_GROUP, &ids[0]).unwrap(), 0);
+
+        assert_eq!(q.queue.purge_dead_letters(NS, DEFAULT_GROUP, None).unwrap(), 1);
+        assert!(q.queue.dead_letters(NS, DEFAULT_GROUP).unwrap().is_empty());
+    }
+
+    #[test]
+    fn lease_pages_continue_and_cursor_moves_over_settled_events_only() {
+        let q = TestQueue::new();
+        q.queue.join_group(NS, "a").unwrap();
+        q.queue.join_group(NS, "b").unwrap();
+        let ids = q.publish(5);
+        let seq = |id: &str| q.queue.seq_of(NS, id).unwrap();
+
+        let first = q.queue.lease_events(NS, "a", None, 2).unwrap();
+        assert_eq!(first.events.iter().map(|ev| &ev.id).collect::<Vec<_>>(), [&ids[0], &ids[1]]);
+        assert!(first.has_more);
+        assert_eq!(first.cursor, None, "leased events are not settled");
+
+        // Group b keeps the acked events stored; the gap at ids[0] keeps a's offset back.
+        assert!(q.queue.ack(NS, "a", &ids[1]).unwrap());
+        let rest = q.queue.lease_events(NS, "a", first.cursor, 2).unwrap();
+        assert_eq!(rest.events.iter().map(|ev| &ev.id).collect::<Vec<_>>(), [&ids[2], &ids[3]]);
+        assert!(rest.has_more);
+        let last = q.queue.lease_events(NS, "a", rest.cursor, 2).unwrap();
+        assert_eq!(last.events.iter().map(|ev| &ev.id).collect::<Vec<_>>(), [&ids[4]]);
+        assert!(!last.has_more);
+
+        assert!(q.queue.ack(NS, "a", &ids[2]).unwrap());
+        let after_gap = q.queue.lease_events(NS, "a", seq(&ids[0]), 10).unwrap();
+        assert_eq!(after_gap.cursor, seq(&ids[2]), "moves over the settled run only");
+        assert!(after_gap.events.is_empty());
+    }
+
+    fn stored_ids(q: &TestQueue) -> Vec<String> {
+        q.queue.fetch_events(NS, None).unwrap().into_iter().map(|ev| ev.id).collect()
+    }
+
+    #[test]
+    fn retention_drops_the_oldest_events_over_the_count() {
+        let q = TestQueue::with_policy(NamespacePolicy { retention_max_count: Some(3), ..NamespacePolicy::default() });
+        let ids = q.publish(5);
+        assert!(q.queue.ack(NS, DEFAULT_GROUP, &ids[4]).unwrap());
+        assert_eq!(stored_ids(&q), ids, "acked events are retained");
+
+        assert_eq!(q.queue.compact().unwrap(), 2);
+        assert_eq!(stored_ids(&q), ids[2..]);
+        assert_eq!(q.queue.compact().unwrap(), 0);
+    }
+
+    #[test]
+    fn retention_drops_events_older_than_the_max_age() {
+        let q = TestQueue::with_policy(NamespacePolicy { retention_max_age_secs: Some(60), ..NamespacePolicy::default() });
+        let now = (now_ms() / 1000) as i64;
+        let old = [q.store(Event { timestamp: now - 120, ..event() }), q.store(Event { timestamp: now - 61, ..event() })];
+        let fresh = [q.store(Event { timestamp: now - 30, ..event() }), q.store(Event { timestamp: 0, ..event() })];
+
+        assert_eq!(q.queue.compact().unwrap(), old.len());
+        assert_eq!(stored_ids(&q), fresh);
+    }
+
+    #[test]
+    fn retention_drops_the_oldest_events_over_the_byte_limit() {
+        let q = TestQueue::new();
+        let ids = q.publish(4);
+        let sizes: Vec<u64> = q.queue.tree(NS).unwrap().iter().values().map(|v| v.unwrap().len() as u64).collect();
+        let q = TestQueue::with_policy(NamespacePolicy {
+            retention_max_bytes: Some(sizes[2] + sizes[3]),
+            ..NamespacePolicy::default()
+        });
+        for id in &ids {
+            q.store(Event { id: id.clone(), ..event() });
+        }
+
+        assert_eq!(q.queue.compact().unwrap(), 2);
+        assert_eq!(stored_ids(&q), ids[2..]);
+    }
+
+    #[test]
+    fn history_reads_offset_and_timestamp_ranges_without_leasing() {
+        let q = TestQueue::new();
+        let ids: Vec<String> = [100, 200, 300, 400].map(|timestamp| q.store(Event { timestamp, ..event() })).into();
+        let read = |range: HistoryRange, after, max| {
+            let page = q.queue.history(NS, &range, after, max).unwrap();
+            (page.events.into_iter().map(|ev| ev.id).collect::<Vec<_>>(), page.cursor, page.has_more)
+        };
+
+        let (all, _, has_more) = read(HistoryRange::default(), None, 10);
+        assert_eq!(all, ids);
+        assert!(!has_more);
+
+        let from_offset = HistoryRange { from_offset: q.queue.seq_of(NS, &ids[2]).unwrap(), ..HistoryRange::default() };
+        assert_eq!(read(from_offset, None, 10).0, ids[2..]);
+
+        let window = HistoryRange { from_timestamp: Some(200), to_timestamp: Some(400), ..HistoryRange::default() };
+        let (first, cursor, has_more) = read(window, None, 1);
+        assert_eq!(first, ids[1..2]);
+        assert!(has_more);
+        let (rest, _, has_more) = read(window, cursor, 10);
+        assert_eq!(rest, ids[2..3]);
+        assert!(!has_more);
+
+        assert_eq!(q.lease_ids(DEFAULT_GROUP), ids, "history leaves delivery alone");
+    }
+
+    #[test]
+    fn ordering_key_holds_later_events_until_the_earlier_one_settles() {
+        let q = TestQueue::with_policy(NamespacePolicy { max_deliveries: Some(1), ..NamespacePolicy::default() });
+        let keyed = |key: &str| {
+            let metadata = HashMap::from([(ORDERING_KEY.to_string(), key.to_string())]);
+            q.store(Event { metadata, ..event() })
+        };
+        let first = keyed("cart-1");
+        let other = keyed("cart-2");
+        let unkeyed = q.store(event());
+        let second = keyed("cart-1");
+        let third = keyed("cart-1");
+
+        assert_eq!(q.lease_ids(DEFAULT_GROUP), [first.as_str(), &other, &unkeyed]);
+        assert!(!q.queue.lease(NS, DEFAULT_GROUP, &second).unwrap(), "blocked behind the unacked first event");
+
+        assert!(q.queue.ack(NS, DEFAULT_GROUP, &first).unwrap());
+        assert_eq!(q.lease_ids(DEFAULT_GROUP), [second.as_str()]);
+
+        let outcome = q.queue.nack(NS, DEFAULT_GROUP, &second, "boom", Duration::ZERO).unwrap();
+        assert_eq!(outcome, Some(FailureOutcome::DeadLettered(1)));
+        assert_eq!(q.lease_ids(DEFAULT_GROUP), [third.as_str()], "dead-lettering releases the key too");
+    }
+
+    #[test]
+    fn scheduled_events_wait_until_they_are_due() {
+        let q = TestQueue::new();
+        let mut pushed = q.queue.subscribe(NS);
+        let schedule = |ev: Event| {
+            let id = ev.id.clone();
+            assert_eq!(q.queue.enqueue(&event_frame(ev)).unwrap(), None, "held back, not stored");
+            id
+        };
+        let delayed = schedule(Event { delay_ms: 50, ..event() });
+        let at = schedule(Event { deliver_at: now_ms() as i64 + 60, ..event() });
+        let overdue = q.store(Event { deliver_at: now_ms() as i64 - 1000, ..event() });
+
+        assert_eq!(q.queue.release_scheduled().unwrap(), 0);
+        assert_eq!(q.lease_ids(DEFAULT_GROUP), [overdue]);
+        while pushed.try_recv().is_ok() {}
+
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(q.queue.release_scheduled().unwrap(), 2);
+        assert_eq!(q.lease_ids(DEFAULT_GROUP), [delayed.as_str(), &at]);
+        assert!(pushed.try_recv().is_ok(), "released events reach subscribers");
+        assert_eq!(q.queue.release_scheduled().unwrap(), 0);
+    }
+
+    #[test]
+    fn expired_events_are_never_delivered_and_swept_away() {
+        let q = TestQueue::new();
+        let now = now_ms() as i64;
+        let expired = q.store(Event { expires_at: now - 1, ..event() });
+        let live = [q.store(Event { expires_at: now + 60_000, ..event() }), q.store(event())];
+
+        assert_eq!(q.lease_ids(DEFAULT_GROUP), live);
+        assert!(!q.queue.lease(NS, DEFAULT_GROUP, &expired).unwrap());
+        assert_eq!(q.queue.expire_events().unwrap(), 1);
+        assert_eq!(stored_ids(&q), live);
+        assert_eq!(q.queue.expire_events().unwrap(), 0);
+    }
+
+    #[test]
+    fn namespace_ttl_stamps_events_and_can_dead_letter_them() {
+        let q = TestQueue::with_policy(NamespacePolicy {
+            ttl_secs: Some(60),
+            dead_letter_expired: Some(true),
+            ..NamespacePolicy::default()
+        });
+        q.queue.join_group(NS, "a").unwrap();
+        q.queue.join_group(NS, "b").unwrap();
+        let before = now_ms() as i64;
+        let stamped = q.store(event());
+        let expired = q.store(Event { expires_at: before - 1, ..event() });
+
+        let stored = q.queue.fetch_events(NS, None).unwrap();
+        assert!(stored[0].expires_at >= before + 60_000, "the TTL counts from enqueue");
+        assert_eq!(stored[1].expires_at, before - 1, "an explicit expiry wins");
+
+        assert_eq!(q.queue.expire_events().unwrap(), 1);
+        for group in ["a", "b"] {
+            let letters = q.queue.dead_letters(NS, group).unwrap();
+            assert_eq!(letters.len(), 1, "group {group}");
+            assert_eq!(letters[0].event.as_ref().unwrap().id, expired);
+            assert!(letters[0].reasons[0].starts_with("expired at"));
+        }
+        assert_eq!(stored_ids(&q), [stamped], "settled by every group, so deleted");
+    }
+
+    #[test]
+    fn republishes_within_the_dedupe_window_are_dropped() {
+        let q = TestQueue::with_policy(NamespacePolicy { dedupe_window_secs: Some(1), ..NamespacePolicy::default() });
+        let publish = |ev: &Event| q.queue.enqueue_once(&event_frame(ev.clone())).unwrap();
+        let plain = event();
+        let metadata = HashMap::from([(IDEMPOTENCY_KEY.to_string(), "order-42".to_string())]);
+        let keyed = Event { metadata, ..event() };
+
+        assert!(matches!(publish(&plain), Enqueued::Stored(Some(_))));
+        assert!(matches!(publish(&keyed), Enqueued::Stored(Some(_))));
+        assert_eq!(publish(&plain), Enqueued::Duplicate(plain.id.clone()));
+        let retry = Event { id: Uuid::new_v4().to_string(), ..keyed.clone() };
+        assert_eq!(publish(&retry), Enqueued::Duplicate(keyed.id.clone()), "same idempotency key, new id");
+        assert_eq!(stored_ids(&q), [plain.id.as_str(), &keyed.id]);
+        assert_eq!(q.queue.prune_dedupe().unwrap(), 0);
+
+        std::thread::sleep(Duration::from_millis(1100));
+        assert_eq!(q.queue.prune_dedupe().unwrap(), 2);
+        assert!(matches!(publish(&plain), Enqueued::Stored(Some(_))));
     }
 }
@@ -1,11 +1,323 @@
-use std::collections::HashMap;
-use std::sync::Arc;
+use anyhow::{bail, Context, Result};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::pbkdf2;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, SystemTime};
 use tokio::sync::RwLock;
 
-#[derive(Debug)]
+use crate::state::queue::valid_namespace;
+
+/// What a client may do with a namespace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// Fetch and ack events.
+    Read,
+    /// Publish events.
+    Write,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClientMetadata {
     pub client_id: String,
-    pub token: String,
+    /// Salted PBKDF2 hash of the token, see `hash_token`. Never the token itself.
+    pub token_hash: String,
+    #[serde(default, rename = "read")]
+    pub read_namespaces: BTreeSet<String>,
+    #[serde(default, rename = "write")]
+    pub write_namespaces: BTreeSet<String>,
+    /// Client certificate name (CN or SAN) this entry maps to under mTLS.
+    /// Falls back to `client_id` when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cert_subject: Option<String>,
 }
 
+impl ClientMetadata {
+    /// New entry with no grants; `token` is hashed immediately.
+    pub fn new(client_id: String, token: &str) -> Self {
+        Self {
+            client_id,
+            token_hash: hash_token(token),
+            read_namespaces: BTreeSet::new(),
+            write_namespaces: BTreeSet::new(),
+            cert_subject: None,
+        }
+    }
+
+    /// Add a grant. Fails for a name `valid_namespace` rejects.
+    pub fn grant<T: Into<String>>(&mut self, namespace: T, access: Access) -> Result<()> {
+        let ns = namespace.into();
+        check_namespace(&ns)?;
+        match access {
+            Access::Read => self.read_namespaces.insert(ns),
+            Access::Write => self.write_namespaces.insert(ns),
+        };
+        Ok(())
+    }
+
+    /// Remove a grant. Returns whether the client had it.
+    pub fn revoke(&mut self, namespace: &str, access: Access) -> bool {
+        match access {
+            Access::Read => self.read_namespaces.remove(namespace),
+            Access::Write => self.write_namespaces.remove(namespace),
+        }
+    }
+
+    /// Replace the stored hash with one for `token`.
+    pub fn set_token(&mut self, token: &str) {
+        self.token_hash = hash_token(token);
+    }
+
+    pub fn allows(&self, namespace: &str, access: Access) -> bool {
+        match access {
+            Access::Read => self.read_namespaces.contains(namespace),
+            Access::Write => self.write_namespaces.contains(namespace),
+        }
+    }
+
+    /// Constant-time check of a presented token against the stored hash.
+    pub fn verify_token(&self, token: &str) -> bool {
+        verify_token_hash(&self.token_hash, token)
+    }
+
+    /// Whether a verified client certificate with `names` identifies this client.
+    pub fn matches_cert(&self, names: &[String]) -> bool {
+        let expected = self.cert_subject.as_deref().unwrap_or(&self.client_id);
+        names.iter().any(|n| n == expected)
+    }
+
+    /// Whether the client holds any grant at all on `namespace`.
+    pub fn knows(&self, namespace: &str) -> bool {
+        self.allows(namespace, Access::Read) || self.allows(namespace, Access::Write)
+    }
+}
+
+/// Fail with a readable error for a name `valid_namespace` rejects.
+pub fn check_namespace(namespace: &str) -> Result<()> {
+    if !valid_namespace(namespace) {
+        bail!("Invalid namespace {namespace:?}: 1–128 bytes, no '.' or '#'");
+    }
+    Ok(())
+}
+
+/// Keyed by `client_id:{id}`, the same form `ClientBuilder` sends in Auth.
 pub type NamespaceRegistry = Arc<RwLock<HashMap<String, ClientMetadata>>>;
+
+pub fn registry_key(client_id: &str) -> String {
+    format!("client_id:{client_id}")
+}
+
+/// Check `client_id`'s current grants for `namespace`.
+pub async fn is_allowed(registry: &NamespaceRegistry, client_id: &str, namespace: &str, access: Access) -> bool {
+    registry
+        .read()
+        .await
+        .get(client_id)
+        .is_some_and(|meta| meta.allows(namespace, access))
+}
+
+// --- Token hashing ----------------------------------------------------------
+
+// Stored as "pbkdf2-sha256${iterations}${salt hex}${hash hex}". Hashes keep
+// their own iteration count, so raising it leaves older ones verifiable.
+const HASH_SCHEME: &str = "pbkdf2-sha256";
+const HASH_ITERATIONS: u32 = 600_000;
+const SALT_LEN: usize = 16;
+const HASH_LEN: usize = 32;
+
+pub fn hash_token(token: &str) -> String {
+    let mut salt = [0u8; SALT_LEN];
+    SystemRandom::new()
+        .fill(&mut salt)
+        .expect("system RNG unavailable");
+
+    let mut hash = [0u8; HASH_LEN];
+    let iterations = NonZeroU32::new(HASH_ITERATIONS).unwrap();
+    pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256, iterations, &salt, token.as_bytes(), &mut hash);
+
+    format!("{HASH_SCHEME}${HASH_ITERATIONS}${}${}", to_hex(&salt), to_hex(&hash))
+}
+
+/// Random 32-byte token, hex encoded, for new clients and rotations.
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("system RNG unavailable");
+    to_hex(&bytes)
+}
+
+/// Spend as long on `token` as verifying a real client's would, so an
+/// unknown client id cannot be told apart from a wrong token.
+pub fn reject_unknown_client(token: &str) {
+    static DUMMY_HASH: LazyLock<String> = LazyLock::new(|| hash_token(&generate_token()));
+    verify_token_hash(&DUMMY_HASH, token);
+}
+
+fn verify_token_hash(stored: &str, token: &str) -> bool {
+    let mut parts = stored.split('$');
+    let (Some(HASH_SCHEME), Some(iterations), Some(salt), Some(hash), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return false;
+    };
+
+    let (Some(iterations), Some(salt), Some(hash)) = (
+        iterations.parse().ok().and_then(NonZeroU32::new),
+        from_hex(salt),
+        from_hex(hash),
+    ) else {
+        return false;
+    };
+
+    pbkdf2::verify(pbkdf2::PBKDF2_HMAC_SHA256, iterations, &salt, token.as_bytes(), &hash).is_ok()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// --- Persistent store -------------------------------------------------------
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RegistryFile {
+    #[serde(default)]
+    clients: Vec<ClientMetadata>,
+}
+
+/// JSON file holding every registered client.
+#[derive(Debug, Clone)]
+pub struct RegistryStore {
+    path: PathBuf,
+}
+
+impl RegistryStore {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Load all clients, keyed by `client_id:{id}`. A missing file is an empty registry.
+    pub fn load(&self) -> Result<HashMap<String, ClientMetadata>> {
+        if !self.path.exists() {
+            return Ok(HashMap::new());
+        }
+
+        let raw = std::fs::read_to_string(&self.path)
+            .with_context(|| format!("Failed to read registry {}", self.path.display()))?;
+        let file: RegistryFile = serde_json::from_str(&raw)
+            .with_context(|| format!("Invalid registry {}", self.path.display()))?;
+
+        Ok(file
+            .clients
+            .into_iter()
+            .map(|meta| (registry_key(&meta.client_id), meta))
+            .collect())
+    }
+
+    /// Write all clients atomically (temp file + rename).
+    pub fn save(&self, clients: &HashMap<String, ClientMetadata>) -> Result<()> {
+        let mut list: Vec<ClientMetadata> = clients.values().cloned().collect();
+        list.sort_by(|a, b| a.client_id.cmp(&b.client_id));
+
+        let json = serde_json::to_string_pretty(&RegistryFile { clients: list })?;
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let tmp = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp, json).with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, &self.path)
+            .with_context(|| format!("Failed to replace {}", self.path.display()))?;
+        Ok(())
+    }
+
+    /// Write `meta` as the only client if the store has none yet. Returns whether it did.
+    pub fn seed_if_empty(&self, meta: ClientMetadata) -> Result<bool> {
+        let mut clients = self.load()?;
+        if !clients.is_empty() {
+            return Ok(false);
+        }
+        clients.insert(registry_key(&meta.client_id), meta);
+        self.save(&clients)?;
+        Ok(true)
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        std::fs::metadata(&self.path).and_then(|m| m.modified()).ok()
+    }
+
+    /// Reload into the live registry. On error the current contents are kept.
+    pub async fn reload_into(&self, registry: &NamespaceRegistry) {
+        match self.load() {
+            Ok(map) => {
+                let count = map.len();
+                *registry.write().await = map;
+                println!("🔄 Registry reloaded: {count} client(s)");
+            }
+            Err(e) => eprintln!("❌ Registry reload failed, keeping previous entries: {e:#}"),
+        }
+    }
+
+    /// Reload whenever the file's mtime changes (polled) or on SIGHUP.
+    /// Existing connections keep running; handlers see the new grants on their next frame.
+    pub fn spawn_reloader(self, registry: NamespaceRegistry, poll: Duration) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut last_seen = self.modified();
+            let mut ticker = tokio::time::interval(poll);
+
+            #[cfg(unix)]
+            let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+                Ok(s) => Some(s),
+                Err(e) => {
+                    eprintln!("⚠️ SIGHUP reload unavailable: {e}");
+                    None
+                }
+            };
+
+            loop {
+                #[cfg(unix)]
+                let hup = async {
+                    match hangup.as_mut() {
+                        Some(s) => s.recv().await,
+                        None => std::future::pending().await,
+                    }
+                };
+                #[cfg(not(unix))]
+                let hup = std::future::pending::<Option<()>>();
+
+                tokio::select! {
+                    _ = ticker.tick() => {
+                        let modified = self.modified();
+                        if modified == last_seen {
+                            continue;
+                        }
+                        last_seen = modified;
+                    }
+                    _ = hup => {
+                        println!("📣 SIGHUP received");
+                        last_seen = self.modified();
+                    }
+                }
+
+                self.reload_into(&registry).await;
+            }
+        })
+    }
+}
@@ -1,34 +1,190 @@
-// This file generates a self-signed TLS certificate for use in testing or local development.
-// It uses the `rcgen` crate to create the certificate and `quinn` for the TLS types.
-// The generated certificate is suitable for use with the `quinn` library.
-// Returns a tuple containing the `Certificate` and `PrivateKey`.
-// This code is intended for use in a Rust project that requires TLS functionality.
-// The `generate_cert` function can be called to obtain a self-signed certificate and private key.
+// TLS material for the QUIC endpoints.
+// Production: the server loads a PEM (or DER) certificate chain and private key
+// from configured paths, and clients verify it against a configured CA bundle.
+// Dev mode: `dev_self_signed` generates a self-signed `localhost` certificate
+// with `rcgen` (or reuses the one from a previous run). Clients can then point
+// their CA bundle at the generated certificate.
+// mTLS (optional): the server additionally requires a client certificate signed
+// by a configured CA; `cert_identities` extracts the names it can be mapped by.
 // Usage:
-// let (cert, key) = generate_cert();   
-// This is used in the context of a QUIC server.
+// let server = server_config(load_cert_chain(&cert_path)?, load_private_key(&key_path)?, None)?;
+// let client = client_config(load_ca_roots(&ca_path)?, None)?;
 
-use rcgen::{generate_simple_self_signed};
-use rustls::{Certificate, PrivateKey};
+use anyhow::{bail, Context, Result};
+use quinn::{ClientConfig, ServerConfig};
+use rcgen::generate_simple_self_signed;
+use rustls::server::AllowAnyAuthenticatedClient;
+use rustls::{
+    Certificate,
+    ClientConfig as RustlsClientConfig,
+    PrivateKey,
+    RootCertStore,
+    ServerConfig as RustlsServerConfig,
+};
+use rustls_pemfile::Item;
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::{FromDer, X509Certificate};
 use std::fs;
+use std::io::BufReader;
 use std::path::Path;
+use std::sync::Arc;
 
-const CERT_PATH: &str = "cert.der";
-const KEY_PATH: &str = "key.der";
+fn is_pem(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"-----BEGIN")
+}
+
+fn read_file(path: &Path, what: &str) -> Result<Vec<u8>> {
+    fs::read(path).with_context(|| format!("Failed to read {what} {}", path.display()))
+}
+
+/// Load a certificate chain, leaf first. Accepts a PEM bundle or a single DER certificate.
+pub fn load_cert_chain(path: &Path) -> Result<Vec<Certificate>> {
+    let bytes = read_file(path, "certificate")?;
+    if !is_pem(&bytes) {
+        return Ok(vec![Certificate(bytes)]);
+    }
+
+    let certs = rustls_pemfile::certs(&mut BufReader::new(bytes.as_slice()))
+        .with_context(|| format!("Invalid PEM in certificate {}", path.display()))?;
+    if certs.is_empty() {
+        bail!("No certificates found in {}", path.display());
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
 
-pub fn generate_or_load_cert() -> (Vec<Certificate>, PrivateKey) {
-    if Path::new(CERT_PATH).exists() && Path::new(KEY_PATH).exists() {
-        let cert = fs::read(CERT_PATH).unwrap();
-        let key = fs::read(KEY_PATH).unwrap();
-        return (vec![Certificate(cert)], PrivateKey(key));
+/// Load a private key. Accepts PEM (PKCS#8, RSA or SEC1 EC) or a DER PKCS#8 key.
+pub fn load_private_key(path: &Path) -> Result<PrivateKey> {
+    let bytes = read_file(path, "private key")?;
+    if !is_pem(&bytes) {
+        return Ok(PrivateKey(bytes));
     }
 
-    let rcgen_cert = generate_simple_self_signed(vec!["localhost".into()]).unwrap();
-    let cert_der = rcgen_cert.serialize_der().unwrap();
+    let items = rustls_pemfile::read_all(&mut BufReader::new(bytes.as_slice()))
+        .with_context(|| format!("Invalid PEM in private key {}", path.display()))?;
+    items
+        .into_iter()
+        .find_map(|item| match item {
+            Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .with_context(|| format!("No private key found in {}", path.display()))
+}
+
+/// Load a CA bundle (PEM, or a single DER certificate) as trust roots.
+pub fn load_ca_roots(path: &Path) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_cert_chain(path)? {
+        roots
+            .add(&cert)
+            .with_context(|| format!("Invalid CA certificate in {}", path.display()))?;
+    }
+    Ok(roots)
+}
+
+/// Dev mode only: reuse the certificate/key at the given paths, or generate a
+/// self-signed `localhost` pair there if either is missing.
+pub fn dev_self_signed(cert_path: &Path, key_path: &Path) -> Result<(Vec<Certificate>, PrivateKey)> {
+    if cert_path.exists() && key_path.exists() {
+        return Ok((load_cert_chain(cert_path)?, load_private_key(key_path)?));
+    }
+
+    let rcgen_cert = generate_simple_self_signed(vec!["localhost".into()])
+        .context("Failed to generate self-signed certificate")?;
+    let cert_der = rcgen_cert
+        .serialize_der()
+        .context("Failed to serialize self-signed certificate")?;
     let key_der = rcgen_cert.serialize_private_key_der();
 
-    fs::write(CERT_PATH, &cert_der).unwrap();
-    fs::write(KEY_PATH, &key_der).unwrap();
+    fs::write(cert_path, &cert_der)
+        .with_context(|| format!("Failed to write {}", cert_path.display()))?;
+    fs::write(key_path, &key_der)
+        .with_context(|| format!("Failed to write {}", key_path.display()))?;
+
+    println!("⚠️ Generated self-signed dev certificate at {}", cert_path.display());
+    Ok((vec![Certificate(cert_der)], PrivateKey(key_der)))
+}
+
+/// Build the QUIC server config. With `client_ca`, every client must present a
+/// certificate chaining to one of those roots (mTLS).
+pub fn server_config(
+    cert_chain: Vec<Certificate>,
+    key: PrivateKey,
+    client_ca: Option<RootCertStore>,
+) -> Result<ServerConfig> {
+    // Same TLS 1.3-only defaults `quinn::ServerConfig::with_single_cert` uses.
+    let builder = RustlsServerConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .context("TLS 1.3 unsupported by rustls build")?;
+
+    let builder = match client_ca {
+        Some(roots) => builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed()),
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut crypto = builder
+        .with_single_cert(cert_chain, key)
+        .context("Certificate and private key do not form a valid server identity")?;
+    crypto.max_early_data_size = u32::MAX;
+
+    Ok(ServerConfig::with_crypto(Arc::new(crypto)))
+}
+
+/// Build the QUIC client config. `identity` is the client certificate chain and
+/// key presented when the server requires mTLS.
+pub fn client_config(
+    roots: RootCertStore,
+    identity: Option<(Vec<Certificate>, PrivateKey)>,
+) -> Result<ClientConfig> {
+    let builder = RustlsClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots);
+
+    let client_crypto = match identity {
+        Some((chain, key)) => builder
+            .with_client_auth_cert(chain, key)
+            .context("Client certificate and private key do not match")?,
+        None => builder.with_no_client_auth(),
+    };
 
-    (vec![Certificate(cert_der)], PrivateKey(key_der))
+    Ok(ClientConfig::new(Arc::new(client_crypto)))
+}
+
+/// Names a verified client certificate can be mapped by: the subject CN plus
+/// DNS, URI and email SANs.
+pub fn cert_identities(cert: &Certificate) -> Result<Vec<String>> {
+    let (_, parsed) = X509Certificate::from_der(&cert.0).context("Invalid client certificate")?;
+    let mut names: Vec<String> = parsed
+        .subject()
+        .iter_common_name()
+        .filter_map(|cn| cn.as_str().ok().map(str::to_string))
+        .collect();
+
+    if let Ok(Some(san)) = parsed.subject_alternative_name() {
+        for name in &san.value.general_names {
+            match name {
+                GeneralName::DNSName(n) | GeneralName::URI(n) | GeneralName::RFC822Name(n) => {
+                    names.push(n.to_string())
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(names)
+}
+
+/// Identities of the peer's leaf certificate on an established connection,
+/// or `None` if it presented no certificate.
+pub fn peer_identities(conn: &quinn::Connection) -> Option<Vec<String>> {
+    let chain = conn.peer_identity()?.downcast::<Vec<Certificate>>().ok()?;
+    let leaf = chain.first()?;
+    match cert_identities(leaf) {
+        Ok(names) => Some(names),
+        Err(e) => {
+            eprintln!("❌ {e:#}");
+            None
+        }
+    }
 }
@@ -1,5 +1,5 @@
 // utils.rs
-use crate::state::registry::NamespaceRegistry;
+use crate::state::registry::{reject_unknown_client, NamespaceRegistry};
 use crate::protocol::h3x as pb;
 use std::time::{SystemTime, UNIX_EPOCH};
 
@@ -10,18 +10,41 @@ pub fn now_ms() -> u64 {
         .as_millis() as u64
 }
 
-pub async fn validate_auth(payload: &pb::Auth, registry: &NamespaceRegistry) -> bool {
-    let map = registry.read().await;
+/// Check an Auth frame against the registry. `peer_names` are the verified
+/// client certificate names when the server runs with mTLS.
+pub async fn validate_auth(payload: &pb::Auth, registry: &NamespaceRegistry, peer_names: Option<&[String]>) -> bool {
     let id = &payload.client_id;
+    let token = payload.token.clone();
 
+    // Hashing takes a while: run it off the runtime, without holding the registry lock.
+    let Some(meta) = registry.read().await.get(id).cloned() else {
+        // Hash anyway so an unknown id takes as long to reject as a wrong token.
+        let _ = tokio::task::spawn_blocking(move || reject_unknown_client(&token)).await;
+        println!("❌ No such client ID in registry: {}", id);
+        return false;
+    };
+    let Ok((meta, true)) = tokio::task::spawn_blocking(move || {
+        let verified = meta.verify_token(&token);
+        (meta, verified)
+    })
+    .await
+    else {
+        return false;
+    };
 
-    match map.get(id) {
-        Some(meta) => {
-            meta.token == payload.token
-        }
-        None => {
-            println!("❌ No such client ID in registry: {}", id);
+    if let Some(names) = peer_names
+        && !meta.matches_cert(names)
+    {
+        println!("❌ Client certificate {:?} does not belong to {}", names, id);
+        return false;
+    }
+
+    // Every namespace the client asks for must be granted up front.
+    match payload.namespaces.iter().find(|ns| !meta.knows(ns)) {
+        Some(ns) => {
+            println!("❌ Client {} has no grant for namespace: {}", id, ns);
             false
         }
+        None => true,
     }
 }
\ No newline at end of file
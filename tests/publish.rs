@@ -0,0 +1,76 @@
+// End-to-end publish checks against a server on a free local port.
+
+use std::net::{SocketAddr, UdpSocket};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use h3x::client::builder::ClientBuilder;
+use h3x::client::start_client;
+use h3x::server::builder::ServerBuilder;
+use h3x::server::run_server;
+use h3x::state::registry::{Access, ClientMetadata, RegistryStore};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+/// Longer than any rejection should take, shorter than the publish ack timeout.
+const REPLY_WITHIN: Duration = Duration::from_secs(10);
+
+fn free_addr() -> SocketAddr {
+    UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+}
+
+/// Start a dev-TLS server whose only client `pub` may write to `orders`.
+/// Returns its address and data directory once its certificate exists.
+async fn start_server() -> (SocketAddr, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("h3x-test-{}", Uuid::new_v4()));
+    let mut meta = ClientMetadata::new("pub".into(), "pt");
+    meta.grant("orders", Access::Write).unwrap();
+    RegistryStore::new(dir.join("registry.json")).seed_if_empty(meta).unwrap();
+
+    let addr = free_addr();
+    let params = ServerBuilder::new()
+        .bind_addr(addr)
+        .data_dir(&dir)
+        .cert_path(dir.join("cert.pem"))
+        .key_path(dir.join("key.pem"))
+        .tls_dev(true)
+        .build()
+        .unwrap();
+    tokio::spawn(run_server(params));
+
+    for _ in 0..100 {
+        if dir.join("cert.pem").exists() {
+            return (addr, dir);
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("server did not write its certificate");
+}
+
+#[tokio::test]
+async fn publish_to_ungranted_namespace_fails() {
+    let (addr, dir) = start_server().await;
+    let params = ClientBuilder::new()
+        .client_id("pub")
+        .token("pt")
+        .namespace("orders")
+        .remote_addr(addr)
+        .ca_path(dir.join("cert.pem"))
+        .publish_ack_timeout(REPLY_WITHIN * 3)
+        .build()
+        .unwrap();
+    let cancel = CancellationToken::new();
+    let (publisher, _client) = start_client(params, cancel.clone());
+
+    let granted = tokio::time::timeout(REPLY_WITHIN, publisher.publish("orders", "T", "ok", Vec::new())).await;
+    assert!(matches!(granted, Ok(Ok(_))), "publish to a granted namespace: {granted:?}");
+
+    let forbidden = tokio::time::timeout(REPLY_WITHIN, publisher.publish("secret", "T", "no", Vec::new()))
+        .await
+        .expect("rejection should not wait for the ack timeout");
+    let error = forbidden.expect_err("publish to an ungranted namespace must fail");
+    assert!(error.to_string().contains("ForbiddenNamespace"), "{error}");
+
+    cancel.cancel();
+    let _ = std::fs::remove_dir_all(dir);
+}